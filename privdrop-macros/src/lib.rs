@@ -0,0 +1,45 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::parse::Parser;
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, ItemFn, Meta, Token};
+
+/// Drop privileges before running the body of the annotated function
+///
+/// The arguments are the options accepted by the `privdrop!` macro, written as
+/// `name = value`, `name(a, b)` or `name`. The changes are applied and verified with
+/// `privdrop::run()` before any of the function's code runs.
+///
+/// # Example
+/// ```ignore
+/// #[privdrop::main(chroot = "/var/empty", user = "nobody", no_new_privs)]
+/// fn main() {
+///     // Runs as `nobody`, confined to `/var/empty`
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn main(args: TokenStream, item: TokenStream) -> TokenStream {
+    let options = match Punctuated::<Meta, Token![,]>::parse_terminated.parse(args) {
+        Ok(options) => options,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let mut function = parse_macro_input!(item as ItemFn);
+    let options = options.iter().map(|option| match option {
+        Meta::Path(name) => quote! { #name },
+        Meta::List(list) => {
+            let name = &list.path;
+            let args = &list.tokens;
+            quote! { #name(#args) }
+        }
+        Meta::NameValue(name_value) => {
+            let name = &name_value.path;
+            let value = &name_value.value;
+            quote! { #name: #value }
+        }
+    });
+    let body = &function.block;
+    function.block = syn::parse_quote! {{
+        ::privdrop::run(::privdrop::privdrop! { #(#options),* }, move || #body)
+    }};
+    quote! { #function }.into()
+}
@@ -0,0 +1,56 @@
+//! Check the credentials of a running process against a privdrop specification
+//!
+//! Usage: `privdrop-verify <pid> <spec>`, where `<spec>` is the output of
+//! `ResolvedPrivDrop::serialize()`, e.g. `uid=65534;gid=65534;groups=65534`.
+//!
+//! The credentials are read from `/proc`, not from the process itself. The exit status is `0`
+//! if the process matches, `1` if it doesn't, and `2` if it couldn't be inspected.
+
+use std::process::exit;
+
+#[cfg(target_os = "linux")]
+fn main() {
+    use privdrop::{ProcessCredentials, ResolvedPrivDrop};
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <pid> <spec>", args[0]);
+        exit(2);
+    }
+    let pid = args[1].parse().unwrap_or_else(|_| {
+        eprintln!("Invalid process ID: {}", args[1]);
+        exit(2)
+    });
+    // Descriptors in a specification would refer to this process, not to the inspected one.
+    if args[2]
+        .split(';')
+        .any(|field| field.split('=').next().unwrap_or("").ends_with("_fd"))
+    {
+        eprintln!("Specifications can't include file descriptors");
+        exit(2);
+    }
+    let expected = ResolvedPrivDrop::deserialize(&args[2]).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(2)
+    });
+    let creds = ProcessCredentials::inspect(pid).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(2)
+    });
+    let mismatches = expected.mismatches(&creds);
+    if mismatches.is_empty() {
+        println!("Process {} matches the specification", pid);
+        return;
+    }
+    println!("Process {} doesn't match the specification:", pid);
+    for mismatch in mismatches {
+        println!("- {}", mismatch);
+    }
+    exit(1);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    eprintln!("Inspecting other processes is only supported on Linux");
+    exit(2);
+}
@@ -0,0 +1,165 @@
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+use std::ffi::CString;
+use std::ffi::OsStr;
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+use std::os::unix::ffi::OsStrExt;
+
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+use super::errors::*;
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+const MAX_BUF_LEN: usize = 1 << 20;
+
+/// Call a reentrant lookup function with a buffer grown as needed, and wipe the buffer before
+/// returning the result of the last call
+#[cfg(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn lookup<F: FnMut(&mut [libc::c_char]) -> libc::c_int>(mut f: F) -> libc::c_int {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let ret = loop {
+        let ret = f(&mut buf);
+        if ret != libc::ERANGE || buf.len() >= MAX_BUF_LEN {
+            break ret;
+        }
+        let len = buf.len() * 2;
+        #[cfg(feature = "zeroize")]
+        buf.zeroize();
+        buf.resize(len, 0);
+    };
+    #[cfg(feature = "zeroize")]
+    buf.zeroize();
+    ret
+}
+
+/// Fail if the account of `user` has expired, according to its shadow entry
+///
+/// Accounts without a shadow entry are accepted.
+#[cfg(target_os = "linux")]
+pub(crate) fn check_not_expired(user: &OsStr) -> Result<(), PrivDropError> {
+    let name = CString::new(user.as_bytes())
+        .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username")))?;
+    let mut spwd = unsafe { std::mem::zeroed::<libc::spwd>() };
+    let mut result = std::ptr::null_mut();
+    let ret = lookup(|buf| unsafe {
+        libc::getspnam_r(
+            name.as_ptr(),
+            &mut spwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    });
+    let expire = spwd.sp_expire;
+    if result.is_null() {
+        if ret != 0 && ret != libc::ENOENT {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Unable to read the shadow entry of the target account",
+            )));
+        }
+        return Ok(());
+    }
+    let today = unsafe { libc::time(std::ptr::null_mut()) } / 86400;
+    if expire > 0 && today as libc::c_long >= expire {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The target account has expired",
+        )));
+    }
+    Ok(())
+}
+
+/// Fail if the account of `user` has expired, according to its password entry
+///
+/// Unknown accounts are accepted.
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+))]
+pub(crate) fn check_not_expired(user: &OsStr) -> Result<(), PrivDropError> {
+    let name = CString::new(user.as_bytes())
+        .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username")))?;
+    let mut pwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = std::ptr::null_mut();
+    let ret = lookup(|buf| unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut pwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    });
+    let expire = pwd.pw_expire;
+    if result.is_null() {
+        if ret != 0 && ret != libc::ENOENT {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Unable to read the password entry of the target account",
+            )));
+        }
+        return Ok(());
+    }
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    if expire > 0 && now >= expire {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The target account has expired",
+        )));
+    }
+    Ok(())
+}
+
+/// Fail if the account of `user` has expired
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+pub(crate) fn check_not_expired(_user: &OsStr) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Checking the expiration of accounts is not supported on this platform",
+    )))
+}
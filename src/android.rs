@@ -0,0 +1,150 @@
+/// Fixed Android IDs (AIDs) from `android_filesystem_config.h`
+const AIDS: &[(&str, libc::uid_t)] = &[
+    ("root", 0),
+    ("system", 1000),
+    ("radio", 1001),
+    ("bluetooth", 1002),
+    ("graphics", 1003),
+    ("input", 1004),
+    ("audio", 1005),
+    ("camera", 1006),
+    ("log", 1007),
+    ("compass", 1008),
+    ("mount", 1009),
+    ("wifi", 1010),
+    ("adb", 1011),
+    ("install", 1012),
+    ("media", 1013),
+    ("dhcp", 1014),
+    ("sdcard_rw", 1015),
+    ("vpn", 1016),
+    ("keystore", 1017),
+    ("usb", 1018),
+    ("drm", 1019),
+    ("mdnsr", 1020),
+    ("gps", 1021),
+    ("media_rw", 1023),
+    ("mtp", 1024),
+    ("drmrpc", 1026),
+    ("nfc", 1027),
+    ("sdcard_r", 1028),
+    ("clat", 1029),
+    ("loop_radio", 1030),
+    ("mediadrm", 1031),
+    ("package_info", 1032),
+    ("sdcard_pics", 1033),
+    ("sdcard_av", 1034),
+    ("sdcard_all", 1035),
+    ("logd", 1036),
+    ("shared_relro", 1037),
+    ("dbus", 1038),
+    ("tlsdate", 1039),
+    ("mediaex", 1040),
+    ("audioserver", 1041),
+    ("metrics_coll", 1042),
+    ("metricsd", 1043),
+    ("webserv", 1044),
+    ("debuggerd", 1045),
+    ("mediacodec", 1046),
+    ("cameraserver", 1047),
+    ("firewall", 1048),
+    ("trunks", 1049),
+    ("nvram", 1050),
+    ("dns", 1051),
+    ("dns_tether", 1052),
+    ("webview_zygote", 1053),
+    ("vehicle_network", 1054),
+    ("media_audio", 1055),
+    ("media_video", 1056),
+    ("media_image", 1057),
+    ("tombstoned", 1058),
+    ("media_obb", 1059),
+    ("ese", 1060),
+    ("ota_update", 1061),
+    ("automotive_evs", 1062),
+    ("lowpan", 1063),
+    ("hsm", 1064),
+    ("reserved_disk", 1065),
+    ("statsd", 1066),
+    ("incidentd", 1067),
+    ("secure_element", 1068),
+    ("lmkd", 1069),
+    ("llkd", 1070),
+    ("iorapd", 1071),
+    ("gpu_service", 1072),
+    ("network_stack", 1073),
+    ("shell", 2000),
+    ("cache", 2001),
+    ("diag", 2002),
+    ("net_bt_admin", 3001),
+    ("net_bt", 3002),
+    ("inet", 3003),
+    ("net_raw", 3004),
+    ("net_admin", 3005),
+    ("net_bw_stats", 3006),
+    ("net_bw_acct", 3007),
+    ("readproc", 3009),
+    ("wakelock", 3010),
+    ("uhid", 3011),
+    ("everybody", 9997),
+    ("misc", 9998),
+    ("nobody", 9999),
+];
+
+const AID_APP_START: libc::uid_t = 10000;
+const AID_APP_END: libc::uid_t = 19999;
+const AID_ISOLATED_START: libc::uid_t = 90000;
+const AID_ISOLATED_END: libc::uid_t = 99999;
+const AID_SHARED_GID_START: libc::uid_t = 50000;
+const AID_USER_OFFSET: libc::uid_t = 100000;
+
+fn fixed_id(name: &str) -> Option<libc::uid_t> {
+    AIDS.iter()
+        .find(|(aid_name, _)| *aid_name == name)
+        .map(|&(_, id)| id)
+}
+
+/// Map an Android user or group name to its ID
+///
+/// Fixed names such as `system` or `inet` are supported, as well as per-user names:
+/// `u<user>_a<app>` for applications, `u<user>_i<n>` for isolated processes, and
+/// `u<user>_<name>` for fixed IDs in a secondary user.
+pub(crate) fn id_from_name(name: &str) -> Option<libc::uid_t> {
+    if let Some(id) = fixed_id(name) {
+        return Some(id);
+    }
+    let (user, rest) = name.strip_prefix('u')?.split_once('_')?;
+    let user: libc::uid_t = user.parse().ok()?;
+    let base = user.checked_mul(AID_USER_OFFSET)?;
+    let app_id = if let Some(app) = rest.strip_prefix('a').and_then(|x| x.parse().ok()) {
+        AID_APP_START
+            .checked_add(app)
+            .filter(|&id| id <= AID_APP_END)?
+    } else if let Some(n) = rest.strip_prefix('i').and_then(|x| x.parse().ok()) {
+        AID_ISOLATED_START
+            .checked_add(n)
+            .filter(|&id| id <= AID_ISOLATED_END)?
+    } else {
+        fixed_id(rest)?
+    };
+    base.checked_add(app_id)
+}
+
+/// Compute the default supplementary groups of an Android user
+///
+/// Android doesn't have a group database: a user is only a member of the group with the
+/// same name, and applications are also members of their shared group (`all_a<app>`).
+pub(crate) fn default_groups(user: &str, gid: libc::gid_t) -> Vec<libc::gid_t> {
+    let mut groups = vec![gid];
+    if let Some(id) = id_from_name(user) {
+        if !groups.contains(&id) {
+            groups.push(id);
+        }
+        let app_id = id % AID_USER_OFFSET;
+        if (AID_APP_START..=AID_APP_END).contains(&app_id) {
+            let shared_gid = id - app_id + AID_SHARED_GID_START + (app_id - AID_APP_START);
+            groups.push(shared_gid);
+        }
+    }
+    groups
+}
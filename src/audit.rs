@@ -0,0 +1,345 @@
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use nix::unistd;
+
+use super::errors::*;
+use super::resolved::*;
+use super::resolver::*;
+
+pub(crate) struct ExecutableStats {
+    executable: libc::stat,
+    directory: libc::stat,
+}
+
+fn stat(path: &Path) -> Result<libc::stat, PrivDropError> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid path")))?;
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::stat(path.as_ptr(), &mut st) } != 0 {
+        return Err(nix::Error::last().into());
+    }
+    Ok(st)
+}
+
+/// Collect the metadata of the running executable and of its directory, before the chroot
+pub(crate) fn executable_stats() -> Result<ExecutableStats, PrivDropError> {
+    let executable_path = std::env::current_exe().map_err(|_| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to locate the current executable",
+        ))
+    })?;
+    let directory_path = executable_path.parent().ok_or_else(|| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to locate the directory of the current executable",
+        ))
+    })?;
+    Ok(ExecutableStats {
+        executable: stat(&executable_path)?,
+        directory: stat(directory_path)?,
+    })
+}
+
+/// Check that the home directory of `user` exists, is owned by `uid`, and is writable
+/// neither by its group nor by other users
+pub(crate) fn check_home_dir(user: &OsStr, uid: libc::uid_t) -> Result<(), PrivDropError> {
+    let home = Resolver::new()
+        .home_dir(user)?
+        .ok_or_else(|| PrivDropError::from((ErrorKind::NotFound, "User not found")))?;
+    let st = stat(Path::new(&home)).map_err(|_| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "The home directory of the target user doesn't exist",
+        ))
+    })?;
+    if st.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The home directory of the target user is not a directory",
+        )));
+    }
+    if st.st_uid != uid {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The home directory is not owned by the target user",
+        )));
+    }
+    if st.st_mode & 0o022 != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The home directory is writable by other users",
+        )));
+    }
+    Ok(())
+}
+
+/// Check if a file could be modified by the current effective credentials
+pub(crate) fn is_writable(
+    st: &libc::stat,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: &[libc::gid_t],
+) -> bool {
+    if uid == 0 {
+        return true;
+    }
+    if st.st_uid == uid {
+        return st.st_mode & 0o200 != 0;
+    }
+    if st.st_gid == gid || groups.contains(&st.st_gid) {
+        return st.st_mode & 0o020 != 0;
+    }
+    st.st_mode & 0o002 != 0
+}
+
+/// Check if a file could be read with the given credentials
+pub(crate) fn is_readable(
+    st: &libc::stat,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    groups: &[libc::gid_t],
+) -> bool {
+    if uid == 0 {
+        return true;
+    }
+    if st.st_uid == uid {
+        return st.st_mode & 0o400 != 0;
+    }
+    if st.st_gid == gid || groups.contains(&st.st_gid) {
+        return st.st_mode & 0o040 != 0;
+    }
+    st.st_mode & 0o004 != 0
+}
+
+/// Fail if the current credentials can modify the executable or its directory
+pub(crate) fn check_executable_not_writable(stats: &ExecutableStats) -> Result<(), PrivDropError> {
+    let uid = unistd::geteuid().as_raw();
+    let gid = unistd::getegid().as_raw();
+    let groups = ResolvedPrivDrop::current_groups()?;
+    if is_writable(&stats.executable, uid, gid, &groups) {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The running executable is writable after dropping privileges",
+        )));
+    }
+    if is_writable(&stats.directory, uid, gid, &groups) {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The directory of the running executable is writable after dropping privileges",
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn writable_fd_paths() -> Result<Vec<PathBuf>, PrivDropError> {
+    let entries = std::fs::read_dir("/proc/self/fd").map_err(|_| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to list the open file descriptors",
+        ))
+    })?;
+    let mut fds = vec![];
+    for entry in entries.flatten() {
+        let fd: libc::c_int = match entry.file_name().to_str().and_then(|x| x.parse().ok()) {
+            Some(fd) => fd,
+            None => continue,
+        };
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || flags & libc::O_ACCMODE == libc::O_RDONLY {
+            continue;
+        }
+        if let Ok(path) = std::fs::read_link(entry.path()) {
+            fds.push(path);
+        }
+    }
+    Ok(fds)
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn writable_fd_paths() -> Result<Vec<PathBuf>, PrivDropError> {
+    use std::os::unix::ffi::OsStringExt;
+
+    let max_fd = unsafe { libc::getdtablesize() };
+    let mut fds = vec![];
+    for fd in 0..max_fd {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        if flags < 0 || flags & libc::O_ACCMODE == libc::O_RDONLY {
+            continue;
+        }
+        let mut buf = vec![0u8; libc::PATH_MAX as usize];
+        if unsafe { libc::fcntl(fd, libc::F_GETPATH, buf.as_mut_ptr()) } < 0 {
+            continue;
+        }
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        buf.truncate(len);
+        fds.push(PathBuf::from(std::ffi::OsString::from_vec(buf)));
+    }
+    Ok(fds)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn writable_fd_paths() -> Result<Vec<PathBuf>, PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Listing open file descriptors is not supported on this platform",
+    )))
+}
+
+/// Fail if a writable file descriptor refers to a sensitive location
+pub(crate) fn check_writable_fds(sensitive_paths: &[PathBuf]) -> Result<(), PrivDropError> {
+    let mut sensitive_paths = sensitive_paths.to_vec();
+    sensitive_paths.push(PathBuf::from("/etc"));
+    if let Some(directory) = std::env::current_exe()
+        .ok()
+        .and_then(|x| x.parent().map(|x| x.to_owned()))
+    {
+        sensitive_paths.push(directory);
+    }
+    for path in writable_fd_paths()? {
+        if sensitive_paths.iter().any(|x| path.starts_with(x)) {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "A writable file descriptor refers to a sensitive location",
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Recursively check that a chroot directory contains neither setuid/setgid files nor device nodes
+///
+/// Symbolic links are not followed. The scan fails if the tree is deeper than `max_depth`
+/// or contains more than `max_entries` entries, rather than leaving part of it unchecked.
+#[cfg(feature = "chroot")]
+pub(crate) fn check_chroot_contents(
+    root: &Path,
+    max_depth: usize,
+    max_entries: usize,
+) -> Result<(), PrivDropError> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let mut entries = 0;
+    let mut stack = vec![(root.to_owned(), 0)];
+    while let Some((dir, depth)) = stack.pop() {
+        let read_dir = std::fs::read_dir(&dir).map_err(|_| {
+            PrivDropError::from((
+                ErrorKind::SysError,
+                "Unable to read a directory of the chroot",
+            ))
+        })?;
+        for entry in read_dir {
+            let entry = entry.map_err(|_| {
+                PrivDropError::from((
+                    ErrorKind::SysError,
+                    "Unable to read a directory of the chroot",
+                ))
+            })?;
+            entries += 1;
+            if entries > max_entries {
+                return Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "The chroot directory has too many entries to be audited",
+                )));
+            }
+            let metadata = entry.metadata().map_err(|_| {
+                PrivDropError::from((ErrorKind::SysError, "Unable to inspect a chroot entry"))
+            })?;
+            let file_type = metadata.file_type();
+            if file_type.is_block_device() || file_type.is_char_device() {
+                return Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "The chroot directory contains a device node",
+                )));
+            }
+            if file_type.is_file() && metadata.permissions().mode() & 0o6000 != 0 {
+                return Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "The chroot directory contains a setuid or setgid file",
+                )));
+            }
+            if file_type.is_dir() {
+                if depth >= max_depth {
+                    return Err(PrivDropError::from((
+                        ErrorKind::SysError,
+                        "The chroot directory is too deep to be audited",
+                    )));
+                }
+                stack.push((entry.path(), depth + 1));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fork a child that tries to escape the chroot, and fail if it succeeds
+///
+/// The child walks up the tree with `chdir("..")`, from the working directory and from every
+/// open directory descriptor, and tries to call `chroot()` again, which would allow escaping
+/// using a descriptor to a directory outside of the new root.
+#[cfg(feature = "chroot")]
+pub(crate) fn check_chroot_escape() -> Result<(), PrivDropError> {
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(nix::Error::last().into());
+    }
+    if pid == 0 {
+        let status = if chroot_escape_possible() { 1 } else { 0 };
+        unsafe { libc::_exit(status) };
+    }
+    let mut status = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+        let e = nix::Error::last();
+        if e != nix::Error::EINTR {
+            return Err(e.into());
+        }
+    }
+    if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot can be escaped",
+        )));
+    }
+    Ok(())
+}
+
+/// Only performs async-signal-safe operations, as it runs in a forked child
+#[cfg(feature = "chroot")]
+fn chroot_escape_possible() -> bool {
+    let mut root = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::stat(b"/\0".as_ptr() as *const _, &mut root) } != 0 || walks_out(&root) {
+        return true;
+    }
+    let max_fd = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) }.clamp(0, 65536) as libc::c_int;
+    for fd in 0..max_fd {
+        let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+        if unsafe { libc::fstat(fd, &mut st) } == 0
+            && st.st_mode & libc::S_IFMT == libc::S_IFDIR
+            && unsafe { libc::fchdir(fd) } == 0
+            && walks_out(&root)
+        {
+            return true;
+        }
+    }
+    unsafe { libc::chroot(b".\0".as_ptr() as *const _) == 0 }
+}
+
+#[cfg(feature = "chroot")]
+fn walks_out(root: &libc::stat) -> bool {
+    for _ in 0..256 {
+        if unsafe { libc::chdir(b"..\0".as_ptr() as *const _) } != 0 {
+            break;
+        }
+    }
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    let ret = unsafe { libc::stat(b".\0".as_ptr() as *const _, &mut st) };
+    ret == 0 && (st.st_dev, st.st_ino) != (root.st_dev, root.st_ino)
+}
@@ -0,0 +1,293 @@
+use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use super::errors::*;
+use super::fdpass;
+use super::privdrop::*;
+use super::seccomp;
+use super::worker::*;
+
+#[test]
+fn test_broker_policy() {
+    let allowed_paths = [PathBuf::from("/etc/ssl/key.pem"), PathBuf::from("key.pem")];
+    let allowed = |path: &[u8], flags: libc::c_int| {
+        is_allowed_request(&allowed_paths, &CString::new(path).unwrap(), flags as u64)
+    };
+    assert!(allowed(b"/etc/ssl/key.pem", libc::O_RDONLY));
+    assert!(allowed(
+        b"/etc/ssl/key.pem",
+        libc::O_RDONLY | libc::O_CLOEXEC | libc::O_NOFOLLOW
+    ));
+    assert!(!allowed(b"/etc/ssl/key.pem", libc::O_RDWR));
+    assert!(!allowed(
+        b"/etc/ssl/key.pem",
+        libc::O_RDONLY | libc::O_TRUNC
+    ));
+    assert!(!allowed(
+        b"/etc/ssl/key.pem",
+        libc::O_RDONLY | libc::O_CREAT
+    ));
+    assert!(!allowed(b"/etc/ssl/other.pem", libc::O_RDONLY));
+    assert!(!allowed(b"key.pem", libc::O_RDONLY));
+}
+
+/// Flags that the worker may pass when opening a brokered path, besides `O_RDONLY`
+const ALLOWED_FLAGS: libc::c_int = libc::O_CLOEXEC | libc::O_NOFOLLOW | libc::O_DIRECTORY;
+
+/// Privileged side of a brokered worker created by `PrivDrop::apply_in_brokered_child()`
+///
+/// The worker's attempts to open the allowed paths are forwarded to this process, which
+/// opens them with its own privileges and installs the resulting descriptors in the worker.
+#[derive(Debug)]
+pub struct Broker {
+    listener: OwnedFd,
+    allowed_paths: Vec<PathBuf>,
+}
+
+impl PrivDrop {
+    /// Fork a worker process that applies the changes, then runs `f` with access to the given
+    /// files brokered by the current, privileged process
+    ///
+    /// In the worker, `open()`, `openat()` and `openat2()` calls are intercepted using seccomp
+    /// user notifications. Read-only opens of the absolute paths listed in `allowed_paths`,
+    /// with no flags other than `O_CLOEXEC`, `O_NOFOLLOW` and `O_DIRECTORY`, are performed by
+    /// the broker, using its own credentials and root directory. Other calls are performed by
+    /// the worker itself, with its unprivileged credentials.
+    ///
+    /// The parent must call `Broker::serve()` to service the worker's requests.
+    pub fn apply_in_brokered_child<P: AsRef<Path>, F: FnOnce() -> i32>(
+        &self,
+        allowed_paths: &[P],
+        f: F,
+    ) -> Result<(Worker, Broker), PrivDropError> {
+        let mut sockets = [0; 2];
+        if unsafe {
+            libc::socketpair(
+                libc::AF_UNIX,
+                libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+                0,
+                sockets.as_mut_ptr(),
+            )
+        } != 0
+        {
+            return Err(nix::Error::last().into());
+        }
+        let (parent_socket, child_socket) = unsafe {
+            (
+                OwnedFd::from_raw_fd(sockets[0]),
+                OwnedFd::from_raw_fd(sockets[1]),
+            )
+        };
+        let mut filter = seccomp::filter_for_syscalls(
+            &[
+                #[cfg(target_arch = "x86_64")]
+                libc::SYS_open,
+                libc::SYS_openat,
+                libc::SYS_openat2,
+            ],
+            libc::SECCOMP_RET_USER_NOTIF,
+        );
+        let parent_fd = parent_socket.as_raw_fd();
+        let worker = self.apply_in_child(move || {
+            unsafe { libc::close(parent_fd) };
+            let listener = match seccomp::install_filter(
+                &mut filter,
+                libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+            ) {
+                Ok(listener) => listener as libc::c_int,
+                Err(_) => return WORKER_DROP_FAILED,
+            };
+            let sent = fdpass::send_fd(child_socket.as_raw_fd(), listener);
+            unsafe { libc::close(listener) };
+            if sent.is_err() {
+                return WORKER_DROP_FAILED;
+            }
+            drop(child_socket);
+            f()
+        })?;
+        let listener = fdpass::recv_fd(parent_socket.as_raw_fd())?;
+        let broker = Broker {
+            listener,
+            allowed_paths: allowed_paths
+                .iter()
+                .map(|x| x.as_ref().to_owned())
+                .collect(),
+        };
+        Ok((worker, broker))
+    }
+}
+
+impl Broker {
+    /// Service the worker's requests until it terminates
+    pub fn serve(&self) -> Result<(), PrivDropError> {
+        loop {
+            let mut pfd = libc::pollfd {
+                fd: self.listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pfd, 1, -1) } < 0 {
+                let e = nix::Error::last();
+                if e == nix::Error::EINTR {
+                    continue;
+                }
+                return Err(e.into());
+            }
+            if pfd.revents & libc::POLLIN == 0 {
+                return Ok(());
+            }
+            let mut req = unsafe { std::mem::zeroed::<libc::seccomp_notif>() };
+            if unsafe {
+                libc::ioctl(
+                    self.listener.as_raw_fd(),
+                    libc::SECCOMP_IOCTL_NOTIF_RECV,
+                    &mut req,
+                )
+            } != 0
+            {
+                continue;
+            }
+            self.handle(&req);
+        }
+    }
+
+    fn handle(&self, req: &libc::seccomp_notif) {
+        let nr = req.data.nr as libc::c_long;
+        let (path_ptr, flags) = if nr == libc::SYS_openat2 {
+            match read_open_how(req.pid as _, req.data.args[2], req.data.args[3]) {
+                // Resolution restrictions are left to the worker.
+                Some(how) if how.resolve == 0 => (req.data.args[1], how.flags),
+                _ => return self.respond_continue(req.id),
+            }
+        } else if nr == libc::SYS_openat {
+            (req.data.args[1], req.data.args[2])
+        } else {
+            (req.data.args[0], req.data.args[1])
+        };
+        let path = match read_path(req.pid as _, path_ptr) {
+            Some(path) => path,
+            None => return self.respond_continue(req.id),
+        };
+        if !self.is_valid(req.id) || !is_allowed_request(&self.allowed_paths, &path, flags) {
+            return self.respond_continue(req.id);
+        }
+        let flags = flags as libc::c_int;
+        let fd = unsafe {
+            libc::open(
+                path.as_ptr(),
+                libc::O_RDONLY | libc::O_CLOEXEC | (flags & ALLOWED_FLAGS),
+            )
+        };
+        if fd < 0 {
+            return self.respond_error(req.id, nix::Error::last() as i32);
+        }
+        let addfd = libc::seccomp_notif_addfd {
+            id: req.id,
+            flags: libc::SECCOMP_ADDFD_FLAG_SEND as _,
+            srcfd: fd as _,
+            newfd: 0,
+            newfd_flags: (flags as libc::c_int & libc::O_CLOEXEC) as _,
+        };
+        let ret = unsafe {
+            libc::ioctl(
+                self.listener.as_raw_fd(),
+                libc::SECCOMP_IOCTL_NOTIF_ADDFD,
+                &addfd,
+            )
+        };
+        let e = nix::Error::last();
+        unsafe { libc::close(fd) };
+        if ret < 0 {
+            self.respond_error(req.id, e as i32);
+        }
+    }
+
+    fn is_valid(&self, id: u64) -> bool {
+        unsafe {
+            libc::ioctl(
+                self.listener.as_raw_fd(),
+                libc::SECCOMP_IOCTL_NOTIF_ID_VALID,
+                &id,
+            ) == 0
+        }
+    }
+
+    fn respond(&self, id: u64, error: i32, flags: u32) {
+        let mut resp = libc::seccomp_notif_resp {
+            id,
+            val: 0,
+            error,
+            flags,
+        };
+        unsafe {
+            libc::ioctl(
+                self.listener.as_raw_fd(),
+                libc::SECCOMP_IOCTL_NOTIF_SEND,
+                &mut resp,
+            )
+        };
+    }
+
+    fn respond_continue(&self, id: u64) {
+        self.respond(id, 0, libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as _)
+    }
+
+    fn respond_error(&self, id: u64, errno: i32) {
+        self.respond(id, -errno, 0)
+    }
+}
+
+/// Check that a request only opens an allowed absolute path for reading
+///
+/// A relative path would be resolved against the broker's working directory instead of the
+/// worker's, or against a directory descriptor of the worker, so it is never brokered.
+fn is_allowed_request(allowed_paths: &[PathBuf], path: &CStr, flags: u64) -> bool {
+    let path = path.to_bytes();
+    flags & !(ALLOWED_FLAGS as u64) == libc::O_RDONLY as u64
+        && path.starts_with(b"/")
+        && allowed_paths
+            .iter()
+            .any(|x| x.as_os_str().as_bytes() == path)
+}
+
+fn read_open_how(pid: libc::pid_t, ptr: u64, size: u64) -> Option<libc::open_how> {
+    if size < std::mem::size_of::<libc::open_how>() as u64 {
+        return None;
+    }
+    let mut how = unsafe { std::mem::zeroed::<libc::open_how>() };
+    let local = libc::iovec {
+        iov_base: &mut how as *mut _ as *mut _,
+        iov_len: std::mem::size_of::<libc::open_how>(),
+    };
+    let remote = libc::iovec {
+        iov_base: ptr as *mut _,
+        iov_len: local.iov_len,
+    };
+    let len = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+    if len != local.iov_len as isize {
+        return None;
+    }
+    Some(how)
+}
+
+fn read_path(pid: libc::pid_t, ptr: u64) -> Option<CString> {
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut _,
+        iov_len: buf.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: ptr as *mut _,
+        iov_len: buf.len(),
+    };
+    let len = unsafe { libc::process_vm_readv(pid, &local, 1, &remote, 1, 0) };
+    if len <= 0 {
+        return None;
+    }
+    buf.truncate(len as usize);
+    let nul = buf.iter().position(|&c| c == 0)?;
+    buf.truncate(nul);
+    CString::new(buf).ok()
+}
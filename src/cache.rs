@@ -0,0 +1,90 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+use super::resolved::*;
+
+#[test]
+fn test_cache_locked() {
+    let cache = ResolverCache::new();
+    let config = PrivDrop::default()
+        .user("0")
+        .fallback_to_ids_if_names_are_numeric();
+    // A lock held by a thread that doesn't exist any more after fork() must not block.
+    let guard = cache.entries.lock().unwrap();
+    assert_eq!(cache.resolve(&config).unwrap().uid, Some(0));
+    assert!(cache.get(&config).is_none());
+    drop(guard);
+    cache.resolve(&config).unwrap();
+    assert!(cache.get(&config).is_some());
+}
+
+/// Resolved credentials, shared by a master process and the workers it forks
+///
+/// The master resolves every configuration once, before forking. Workers then apply them
+/// with `PrivDrop::apply_cached()`, without querying the name service, which is both slower
+/// and unsafe after `fork()` for some NSS modules.
+///
+/// Configurations using `ephemeral_ids()` are never cached, as each worker needs its own IDs.
+#[derive(Clone, Debug, Default)]
+pub struct ResolverCache {
+    entries: Arc<Mutex<Vec<(PrivDrop, ResolvedPrivDrop)>>>,
+}
+
+impl ResolverCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the cached credentials for `config`, resolving and caching them if needed
+    ///
+    /// Like `get()`, this never blocks on the cache: if it is in use, the credentials are
+    /// resolved without being cached.
+    pub fn resolve(&self, config: &PrivDrop) -> Result<ResolvedPrivDrop, PrivDropError> {
+        if let Some(resolved) = self.get(config) {
+            return Ok(resolved);
+        }
+        let resolved = config.resolve()?;
+        if config.ephemeral_ids.is_none() {
+            if let Ok(mut entries) = self.entries.try_lock() {
+                entries.push((config.clone(), resolved.clone()));
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Return the cached credentials for `config`, without performing any lookups
+    ///
+    /// This never blocks, so it is safe to call after `fork()` even if another thread was
+    /// using the cache at that time; `None` is returned in that case.
+    pub fn get(&self, config: &PrivDrop) -> Option<ResolvedPrivDrop> {
+        let entries = self.entries.try_lock().ok()?;
+        entries
+            .iter()
+            .find(|(cached_config, _)| cached_config == config)
+            .map(|(_, resolved)| resolved.clone())
+    }
+}
+
+impl PrivDrop {
+    /// Like `apply_with_report()`, using credentials from the cache when available
+    ///
+    /// The checks are performed as usual, but names are only resolved if the cache doesn't
+    /// contain this configuration yet. The reported resolution time is zero for cached
+    /// credentials.
+    pub fn apply_cached(&self, cache: &ResolverCache) -> Result<AppliedPrivileges, PrivDropError> {
+        let res = match cache.get(self) {
+            Some(resolved) => Ok((resolved, Duration::default())),
+            None => Self::preload().and_then(|_| {
+                let start = Instant::now();
+                Ok((cache.resolve(self)?, start.elapsed()))
+            }),
+        }
+        .and_then(|(resolved, resolution)| self.apply_resolved(resolved, resolution, || Ok(())))
+        .map(|(report, _)| report);
+        res.map_err(|e| self.explain(e))
+    }
+}
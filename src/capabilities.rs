@@ -0,0 +1,53 @@
+use caps::{CapSet, Capability, CapsHashSet};
+
+use super::errors::*;
+
+pub(crate) fn parse_caps(names: &[String]) -> Result<Vec<Capability>, PrivDropError> {
+    let mut keep_caps = Vec::with_capacity(names.len());
+    for name in names {
+        let cap = caps::to_canonical(name)
+            .parse()
+            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Unknown capability")))?;
+        if !keep_caps.contains(&cap) {
+            keep_caps.push(cap);
+        }
+    }
+    Ok(keep_caps)
+}
+
+/// Ask the kernel to keep the permitted capabilities through the upcoming uid change
+pub(crate) fn keep_through_setuid(keep_caps: &[Capability]) -> Result<(), PrivDropError> {
+    if keep_caps.is_empty() {
+        return Ok(());
+    }
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) } != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to keep capabilities across the uid change",
+        )));
+    }
+    Ok(())
+}
+
+/// Restrict all capability sets to the retained capabilities, after the uid change
+pub(crate) fn restrict_to(keep_caps: &[Capability]) -> Result<(), PrivDropError> {
+    if keep_caps.is_empty() {
+        return Ok(());
+    }
+    let set_error = |_| PrivDropError::from((ErrorKind::SysError, "Unable to set capabilities"));
+    let keep_set: CapsHashSet = keep_caps.iter().cloned().collect();
+    caps::set(None, CapSet::Permitted, &keep_set).map_err(set_error)?;
+    caps::set(None, CapSet::Effective, &keep_set).map_err(set_error)?;
+    caps::set(None, CapSet::Inheritable, &keep_set).map_err(set_error)?;
+    caps::clear(None, CapSet::Ambient).map_err(set_error)?;
+    for &cap in keep_caps {
+        caps::raise(None, CapSet::Ambient, cap).map_err(set_error)?;
+    }
+    if unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) } != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to reset the keep capabilities flag",
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,298 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::sync::Arc;
+
+use nix::errno::Errno;
+
+use super::errors::*;
+use super::mounts;
+use super::privdrop::*;
+
+#[test]
+fn test_chroot_fd_ownership() {
+    let fd = OwnedFd::from(std::fs::File::open("/").unwrap());
+    let raw_fd = fd.as_raw_fd();
+    let config = PrivDrop::default().chroot_fd(fd);
+    let resolved = config.clone().resolve().unwrap();
+    let again = config.resolve().unwrap();
+    drop(config);
+    assert_eq!(resolved, again);
+    drop(again);
+    let chroot_fd = resolved.chroot_fd.as_ref().unwrap();
+    assert_eq!(chroot_fd.as_raw_fd(), raw_fd);
+    assert_eq!(Arc::strong_count(&chroot_fd.0), 1);
+    resolved.check().unwrap();
+}
+
+/// A chroot directory descriptor, shared by the configurations cloned from the one it was
+/// given to, and closed when the last of them is dropped
+#[derive(Clone, Debug)]
+pub(crate) struct ChrootFd(Arc<OwnedFd>);
+
+impl ChrootFd {
+    pub(crate) fn new(fd: OwnedFd) -> Self {
+        ChrootFd(Arc::new(fd))
+    }
+}
+
+impl AsRawFd for ChrootFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl PartialEq for ChrootFd {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ChrootFd {}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for ChrootFd {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.as_raw_fd())
+    }
+}
+
+#[derive(Debug)]
+enum ChrootDir {
+    Path(CString),
+    Fd(OwnedFd),
+}
+
+/// A change of the root directory, usable without `PrivDrop`
+///
+/// This is what `PrivDrop` uses internally, for programs that confine the filesystem but keep
+/// their user, or change it through other means.
+///
+/// # Example
+/// ```no_run
+/// # use privdrop::Chroot;
+/// # fn main() -> Result<(), privdrop::PrivDropError> {
+/// Chroot::new("/var/empty")?.validate().enter()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Chroot {
+    dir: ChrootDir,
+    overlay: bool,
+    mount_propagation: MountPropagation,
+    validate: bool,
+}
+
+impl Chroot {
+    /// Change the root directory to `path`
+    pub fn new<T: AsRef<Path>>(path: T) -> Result<Self, PrivDropError> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid chroot path")))?;
+        Ok(Self::with_dir(ChrootDir::Path(path)))
+    }
+
+    /// Change the root directory to an open directory, with `fchdir()` followed by
+    /// `chroot(".")`
+    ///
+    /// The descriptor is closed when the `Chroot` is dropped.
+    pub fn from_fd(fd: OwnedFd) -> Self {
+        Self::with_dir(ChrootDir::Fd(fd))
+    }
+
+    fn with_dir(dir: ChrootDir) -> Self {
+        Chroot {
+            dir,
+            overlay: false,
+            mount_propagation: MountPropagation::default(),
+            validate: false,
+        }
+    }
+
+    /// Enter a copy-on-write overlay of the directory in a new mount namespace, discarded on
+    /// exit (Linux only)
+    ///
+    /// See `PrivDrop::chroot_overlay()`.
+    pub fn overlay(mut self) -> Self {
+        self.overlay = true;
+        self
+    }
+
+    /// Set how the mounts of the overlay relate to the parent namespace
+    pub fn mount_propagation(mut self, propagation: MountPropagation) -> Self {
+        self.mount_propagation = propagation;
+        self
+    }
+
+    /// Check that the directory is owned by root and not writable by other users before
+    /// entering it
+    pub fn validate(mut self) -> Self {
+        self.validate = true;
+        self
+    }
+
+    /// Path of the directory, if it was not given as a descriptor
+    pub fn path(&self) -> Option<&Path> {
+        match self.dir {
+            ChrootDir::Path(ref path) => Some(Path::new(OsStr::from_bytes(path.to_bytes()))),
+            ChrootDir::Fd(_) => None,
+        }
+    }
+
+    /// Check that the root directory can be changed, without changing it
+    pub fn check(&self) -> Result<(), PrivDropError> {
+        match self.dir {
+            ChrootDir::Path(ref path) => {
+                check_path(path)?;
+                if self.validate {
+                    validate(path)?;
+                }
+            }
+            ChrootDir::Fd(ref fd) => {
+                check_fd(fd.as_raw_fd())?;
+                if self.overlay {
+                    return Err(PrivDropError::from((
+                        ErrorKind::InvalidConfig,
+                        "An overlay chroot requires a chroot path",
+                    )));
+                }
+                if self.validate {
+                    return Err(PrivDropError::from((
+                        ErrorKind::InvalidConfig,
+                        "Only chroot paths can be validated",
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Perform the checks, then change the root directory, and the current directory to it
+    pub fn enter(&self) -> Result<(), PrivDropError> {
+        self.check()?;
+        match self.dir {
+            ChrootDir::Path(ref path) => enter_path(path, self.overlay, self.mount_propagation),
+            ChrootDir::Fd(ref fd) => enter_fd(fd.as_raw_fd()),
+        }
+    }
+}
+
+/// Check that `path` is an existing directory
+pub(crate) fn check_path(path: &CStr) -> Result<(), PrivDropError> {
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::stat(path.as_ptr(), &mut st) } != 0
+        || st.st_mode & libc::S_IFMT != libc::S_IFDIR
+    {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot directory doesn't exist",
+        )));
+    }
+    Ok(())
+}
+
+/// Check that `fd` is an open directory
+pub(crate) fn check_fd(fd: RawFd) -> Result<(), PrivDropError> {
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut st) } != 0 || st.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot descriptor is not an open directory",
+        )));
+    }
+    Ok(())
+}
+
+/// Check that `workdir` is a directory inside the chroot directory, given as a path or as a
+/// descriptor, and return its path once the root directory has been changed
+pub(crate) fn workdir(
+    chroot: Option<&CStr>,
+    chroot_fd: Option<RawFd>,
+    workdir: &Path,
+) -> Result<CString, PrivDropError> {
+    let workdir = Path::new("/").join(workdir);
+    let relative = match workdir.strip_prefix("/") {
+        Ok(relative) if !relative.as_os_str().is_empty() => relative,
+        _ => Path::new("."),
+    };
+    let invalid = || PrivDropError::from((ErrorKind::InvalidConfig, "Invalid working directory"));
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    let ret = match (chroot, chroot_fd) {
+        (Some(chroot), _) => {
+            let path = Path::new(OsStr::from_bytes(chroot.to_bytes())).join(relative);
+            let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| invalid())?;
+            unsafe { libc::stat(path.as_ptr(), &mut st) }
+        }
+        (None, Some(fd)) => {
+            let path = CString::new(relative.as_os_str().as_bytes()).map_err(|_| invalid())?;
+            unsafe { libc::fstatat(fd, path.as_ptr(), &mut st, 0) }
+        }
+        (None, None) => {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "A working directory in the chroot requires a chroot directory",
+            )))
+        }
+    };
+    if ret != 0 || st.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The working directory doesn't exist in the chroot directory",
+        )));
+    }
+    CString::new(workdir.as_os_str().as_bytes()).map_err(|_| invalid())
+}
+
+/// Check that a chroot directory can't be modified by unprivileged users
+pub(crate) fn validate(path: &CStr) -> Result<(), PrivDropError> {
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe { libc::stat(path.as_ptr(), &mut st) } != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot directory doesn't exist",
+        )));
+    }
+    if st.st_mode & libc::S_IFMT != libc::S_IFDIR {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot path is not a directory",
+        )));
+    }
+    if st.st_uid != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot directory is not owned by root",
+        )));
+    }
+    if st.st_mode & 0o022 != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The chroot directory is writable by other users",
+        )));
+    }
+    Ok(())
+}
+
+/// Change the root directory to `path`, without any checks
+pub(crate) fn enter_path(
+    path: &CStr,
+    overlay: bool,
+    propagation: MountPropagation,
+) -> Result<(), PrivDropError> {
+    if overlay {
+        mounts::mount_chroot_overlay(path, propagation)?;
+    }
+    Errno::result(unsafe { libc::chdir(path.as_ptr()) })?;
+    Errno::result(unsafe { libc::chroot(path.as_ptr()) })?;
+    Errno::result(unsafe { libc::chdir(b"/\0".as_ptr() as *const _) })?;
+    Ok(())
+}
+
+/// Change the root directory to the directory open as `fd`, without any checks
+pub(crate) fn enter_fd(fd: RawFd) -> Result<(), PrivDropError> {
+    Errno::result(unsafe { libc::fchdir(fd) })?;
+    Errno::result(unsafe { libc::chroot(b".\0".as_ptr() as *const _) })?;
+    Errno::result(unsafe { libc::chdir(b"/\0".as_ptr() as *const _) })?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+use nix::unistd;
+
+use super::resolver::*;
+use super::restrictions::*;
+
+/// Inspect the environment to suggest why privileges couldn't be dropped
+pub(crate) fn explain(chroot: Option<&Path>) -> Option<String> {
+    let restrictions = Restrictions::detect();
+    let mut hints = vec![];
+    if let Some(chroot) = chroot {
+        if !chroot.is_dir() {
+            hints.push(format!(
+                "the chroot directory {} doesn't exist, create it first",
+                chroot.display()
+            ));
+        } else if !restrictions.can_chroot {
+            hints.push("grant CAP_SYS_CHROOT to the process, or start it as root".to_string());
+        }
+    }
+    if restrictions.in_user_namespace {
+        hints.push(
+            "the process runs in a user namespace, where only mapped IDs can be used".to_string(),
+        );
+    }
+    if restrictions.setgroups_denied {
+        hints.push(
+            "setgroups() is denied in this user namespace, consider adapt_to_restrictions()"
+                .to_string(),
+        );
+    }
+    if !restrictions.can_setuid || !restrictions.can_setgid {
+        if restrictions.no_new_privs {
+            hints.push(
+                "no_new_privs is set, e.g. by NoNewPrivileges=yes, so privileges can't be regained by executing the program".to_string(),
+            );
+        }
+        if !unistd::geteuid().is_root() {
+            hints.push(
+                "the process is not running as root, start it with sudo or as a system service"
+                    .to_string(),
+            );
+        } else {
+            hints.push("the process runs as root, but lacks CAP_SETUID or CAP_SETGID, check the capability bounding set of the service".to_string());
+        }
+    }
+    if !matches!(Resolver::new().user(OsStr::new("root")), Ok(Some(_))) {
+        hints.push(
+            "the user database is unreachable, check /etc/nsswitch.conf and the NSS services"
+                .to_string(),
+        );
+    }
+    if hints.is_empty() {
+        return None;
+    }
+    Some(hints.join("; "))
+}
@@ -0,0 +1,55 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use nix::errno::Errno;
+
+use super::errors::*;
+
+/// Create a directory if it doesn't exist, then set its owner and mode
+///
+/// Missing parent directories are created with the default owner and mode. The directory
+/// itself is opened without following symbolic links, so that an existing link can't
+/// redirect the change of ownership.
+pub(crate) fn provision(
+    path: &Path,
+    mode: libc::mode_t,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+) -> Result<(), PrivDropError> {
+    let invalid = || PrivDropError::from((ErrorKind::SysError, "Unable to create a directory"));
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|_| invalid())?;
+    }
+    let cpath = CString::new(path.as_os_str().as_bytes()).map_err(|_| invalid())?;
+    if unsafe { libc::mkdir(cpath.as_ptr(), mode) } != 0 && Errno::last() != Errno::EEXIST {
+        return Err(invalid());
+    }
+    let fd = unsafe {
+        libc::open(
+            cpath.as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The path is not a directory",
+        )));
+    }
+    let uid = uid.unwrap_or(!0);
+    let gid = gid.unwrap_or(!0);
+    let ret = unsafe {
+        if libc::fchown(fd, uid, gid) != 0 {
+            -1
+        } else {
+            libc::fchmod(fd, mode)
+        }
+    };
+    let e = nix::Error::last();
+    unsafe { libc::close(fd) };
+    if ret != 0 {
+        return Err(e.into());
+    }
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::ffi::CString;
+use std::ops::RangeInclusive;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::errors::*;
+use super::resolver::*;
+
+#[test]
+fn test_lock_fd() {
+    let open_root = || OwnedFd::from(std::fs::File::open("/").unwrap());
+    let lock = lock_fd(open_root()).unwrap();
+    assert_eq!(lock, lock.clone());
+    assert!(lock_fd(open_root()).is_none());
+    drop(lock);
+    assert!(lock_fd(open_root()).is_some());
+}
+
+/// Lock on an ephemeral ID, released when the last copy is dropped
+#[derive(Clone, Debug)]
+pub struct EphemeralLock(Arc<OwnedFd>);
+
+impl PartialEq for EphemeralLock {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for EphemeralLock {}
+
+impl AsRawFd for EphemeralLock {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Try to take an exclusive lock on `lock_dir/<id>`
+fn lock_id(lock_dir: &Path, id: libc::uid_t) -> Result<Option<EphemeralLock>, PrivDropError> {
+    let path = CString::new(lock_dir.join(id.to_string()).as_os_str().as_bytes())
+        .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid lock directory")))?;
+    let fd = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_RDWR | libc::O_CREAT | libc::O_CLOEXEC,
+            0o600,
+        )
+    };
+    if fd < 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to create a lock file for an ephemeral ID",
+        )));
+    }
+    Ok(lock_fd(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+/// Try to take an exclusive lock on `fd`, held as long as the returned lock is alive
+fn lock_fd(fd: OwnedFd) -> Option<EphemeralLock> {
+    if unsafe { libc::flock(fd.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        return None;
+    }
+    Some(EphemeralLock(Arc::new(fd)))
+}
+
+fn is_unused(resolver: &mut Resolver, id: libc::uid_t) -> bool {
+    matches!(resolver.user_by_uid(id), Ok(None)) && matches!(resolver.group_exists(id), Ok(false))
+}
+
+/// Find an ID within `range` that is neither used by a user nor by a group
+///
+/// If `lock_dir` is set, the ID is also locked there, so that concurrent processes
+/// using the same directory can't allocate the same ID, until the returned lock is dropped.
+pub(crate) fn allocate(
+    range: &RangeInclusive<libc::uid_t>,
+    lock_dir: Option<&Path>,
+) -> Result<(libc::uid_t, Option<EphemeralLock>), PrivDropError> {
+    let mut resolver = Resolver::new();
+    for id in range.clone() {
+        if !is_unused(&mut resolver, id) {
+            continue;
+        }
+        match lock_dir {
+            Some(lock_dir) => match lock_id(lock_dir, id)? {
+                Some(lock) => return Ok((id, Some(lock))),
+                None => continue,
+            },
+            None => return Ok((id, None)),
+        }
+    }
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "No unused ID available in the ephemeral range",
+    )))
+}
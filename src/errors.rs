@@ -1,20 +1,185 @@
 use std::error::Error;
 use std::fmt;
 
+use nix::errno::Errno;
+
+// Exit statuses from sysexits.h
+const EX_NOUSER: i32 = 67;
+const EX_OSERR: i32 = 71;
+const EX_TEMPFAIL: i32 = 75;
+const EX_NOPERM: i32 = 77;
+const EX_CONFIG: i32 = 78;
+
+#[test]
+fn test_serialize() {
+    let errors = [
+        PrivDropError::from(Errno::ENOENT),
+        PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username")),
+        PrivDropError::from((ErrorKind::Timeout, "Timeout; with: separators\nand lines")),
+    ];
+    for e in &errors {
+        let deserialized = PrivDropError::deserialize(&e.serialize()).unwrap();
+        assert_eq!(deserialized.kind(), e.kind());
+        assert_eq!(deserialized.to_string(), e.to_string());
+        assert_eq!(deserialized.exit_code(), e.exit_code());
+    }
+    assert_eq!(
+        PrivDropError::deserialize(&errors[0].serialize()),
+        Some(errors[0].clone())
+    );
+    assert!(PrivDropError::deserialize("").is_none());
+    assert!(PrivDropError::deserialize("Unknown:0:").is_none());
+}
+
+#[test]
+fn test_exit_code_not_found() {
+    let e = PrivDropError::from((ErrorKind::NotFound, "Group not found"));
+    assert_eq!(e.exit_code(), EX_NOUSER);
+}
+
+#[test]
+fn test_exit_code_permission_denied() {
+    let e = PrivDropError::from((
+        ErrorKind::PermissionDenied,
+        "Changing the user is not permitted",
+    ));
+    assert_eq!(e.exit_code(), EX_NOPERM);
+    assert_eq!(PrivDropError::from(Errno::EPERM).exit_code(), EX_NOPERM);
+    assert_eq!(PrivDropError::from(Errno::EACCES).exit_code(), EX_NOPERM);
+}
+
+#[test]
+fn test_exit_code_invalid_config() {
+    let e = PrivDropError::from((ErrorKind::InvalidConfig, "User not found"));
+    assert_eq!(e.exit_code(), EX_CONFIG);
+}
+
+#[test]
+fn test_exit_code_timeout() {
+    let e = PrivDropError::from((ErrorKind::Timeout, "Timeout"));
+    assert_eq!(e.exit_code(), EX_TEMPFAIL);
+}
+
+#[test]
+fn test_exit_code_other_errors() {
+    let e = PrivDropError::from((ErrorKind::SysError, "User not found"));
+    assert_eq!(e.exit_code(), EX_OSERR);
+    let e = PrivDropError::from((ErrorKind::LookupLimit, "Too many lookups"));
+    assert_eq!(e.exit_code(), EX_OSERR);
+    assert_eq!(PrivDropError::from(Errno::ENOENT).exit_code(), EX_OSERR);
+}
+
+#[non_exhaustive]
 #[derive(PartialEq, Eq, Copy, Clone, Debug)]
 pub enum ErrorKind {
     SysError,
+    Timeout,
+    InvalidConfig,
+    LookupLimit,
+    NotFound,
+    PermissionDenied,
+}
+
+impl ErrorKind {
+    fn name(self) -> &'static str {
+        match self {
+            ErrorKind::SysError => "SysError",
+            ErrorKind::Timeout => "Timeout",
+            ErrorKind::InvalidConfig => "InvalidConfig",
+            ErrorKind::LookupLimit => "LookupLimit",
+            ErrorKind::NotFound => "NotFound",
+            ErrorKind::PermissionDenied => "PermissionDenied",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "SysError" => Some(ErrorKind::SysError),
+            "Timeout" => Some(ErrorKind::Timeout),
+            "InvalidConfig" => Some(ErrorKind::InvalidConfig),
+            "LookupLimit" => Some(ErrorKind::LookupLimit),
+            "NotFound" => Some(ErrorKind::NotFound),
+            "PermissionDenied" => Some(ErrorKind::PermissionDenied),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 enum ErrorRepr {
     FromNix(nix::Error),
     WithDescription(ErrorKind, &'static str),
+    /// Error received from another process
+    WithMessage(ErrorKind, String),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct PrivDropError {
     repr: ErrorRepr,
+    hint: Option<String>,
+}
+
+impl PrivDropError {
+    /// Kind of error
+    pub fn kind(&self) -> ErrorKind {
+        match self.repr {
+            ErrorRepr::FromNix(_) => ErrorKind::SysError,
+            ErrorRepr::WithDescription(kind, _) | ErrorRepr::WithMessage(kind, _) => kind,
+        }
+    }
+
+    /// Suggestion on how to fix the problem, if diagnostics were enabled
+    pub fn hint(&self) -> Option<&str> {
+        self.hint.as_deref()
+    }
+
+    /// Conventional exit status for a process that failed with this error, from sysexits.h
+    ///
+    /// `ErrorKind::NotFound` maps to `EX_NOUSER`, `ErrorKind::PermissionDenied` and system
+    /// errors caused by missing privileges to `EX_NOPERM`, `ErrorKind::InvalidConfig` to
+    /// `EX_CONFIG`, `ErrorKind::Timeout` to `EX_TEMPFAIL`, and other errors to `EX_OSERR`.
+    pub fn exit_code(&self) -> i32 {
+        match self.repr {
+            ErrorRepr::FromNix(Errno::EPERM) | ErrorRepr::FromNix(Errno::EACCES) => EX_NOPERM,
+            ErrorRepr::FromNix(_) => EX_OSERR,
+            _ => match self.kind() {
+                ErrorKind::NotFound => EX_NOUSER,
+                ErrorKind::PermissionDenied => EX_NOPERM,
+                ErrorKind::InvalidConfig => EX_CONFIG,
+                ErrorKind::Timeout => EX_TEMPFAIL,
+                ErrorKind::SysError | ErrorKind::LookupLimit => EX_OSERR,
+            },
+        }
+    }
+
+    pub(crate) fn with_hint(mut self, hint: Option<String>) -> Self {
+        self.hint = hint;
+        self
+    }
+
+    /// Serialize the error, e.g. to report it from a forked child
+    pub(crate) fn serialize(&self) -> String {
+        match self.repr {
+            ErrorRepr::FromNix(e) => format!("{}:{}:", self.kind().name(), e as i32),
+            ErrorRepr::WithDescription(kind, description) => {
+                format!("{}:0:{}", kind.name(), description)
+            }
+            ErrorRepr::WithMessage(kind, ref message) => format!("{}:0:{}", kind.name(), message),
+        }
+    }
+
+    /// Reconstruct an error serialized with `serialize()`
+    pub(crate) fn deserialize(serialized: &str) -> Option<Self> {
+        let mut parts = serialized.splitn(3, ':');
+        let kind = ErrorKind::from_name(parts.next()?)?;
+        let errno: i32 = parts.next()?.parse().ok()?;
+        let message = parts.next()?;
+        let repr = match errno {
+            0 => ErrorRepr::WithMessage(kind, message.to_string()),
+            errno => ErrorRepr::FromNix(Errno::from_raw(errno)),
+        };
+        Some(PrivDropError { repr, hint: None })
+    }
 }
 
 impl Error for PrivDropError {
@@ -29,9 +194,14 @@ impl Error for PrivDropError {
 impl fmt::Display for PrivDropError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self.repr {
-            ErrorRepr::FromNix(ref e) => e.fmt(f),
-            ErrorRepr::WithDescription(_, description) => description.fmt(f),
+            ErrorRepr::FromNix(ref e) => e.fmt(f)?,
+            ErrorRepr::WithDescription(_, description) => description.fmt(f)?,
+            ErrorRepr::WithMessage(_, ref message) => message.fmt(f)?,
         }
+        if let Some(ref hint) = self.hint {
+            write!(f, " (hint: {})", hint)?;
+        }
+        Ok(())
     }
 }
 
@@ -39,6 +209,7 @@ impl From<nix::Error> for PrivDropError {
     fn from(e: nix::Error) -> PrivDropError {
         PrivDropError {
             repr: ErrorRepr::FromNix(e),
+            hint: None,
         }
     }
 }
@@ -47,6 +218,59 @@ impl From<(ErrorKind, &'static str)> for PrivDropError {
     fn from((kind, description): (ErrorKind, &'static str)) -> PrivDropError {
         PrivDropError {
             repr: ErrorRepr::WithDescription(kind, description),
+            hint: None,
+        }
+    }
+}
+
+/// All the problems found while validating a configuration
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationErrors {
+    errors: Vec<PrivDropError>,
+}
+
+impl ValidationErrors {
+    /// List of errors, in the order they were found
+    pub fn errors(&self) -> &[PrivDropError] {
+        &self.errors
+    }
+
+    /// Return `true` if no problems were found
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub(crate) fn push(&mut self, error: PrivDropError) {
+        self.errors.push(error);
+    }
+
+    pub(crate) fn check<T>(&mut self, result: Result<T, PrivDropError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.push(e);
+                None
+            }
+        }
+    }
+}
+
+impl Error for ValidationErrors {}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                "; ".fmt(f)?;
+            }
+            error.fmt(f)?;
         }
+        Ok(())
+    }
+}
+
+impl From<PrivDropError> for ValidationErrors {
+    fn from(e: PrivDropError) -> ValidationErrors {
+        ValidationErrors { errors: vec![e] }
     }
 }
@@ -0,0 +1,261 @@
+use std::convert::Infallible;
+#[cfg(feature = "chroot")]
+use std::ffi::CStr;
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+#[cfg(feature = "chroot")]
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(feature = "chroot")]
+use std::path::Component;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "chroot")]
+use std::time::Instant;
+
+use super::errors::*;
+use super::privdrop::*;
+
+#[cfg(feature = "chroot")]
+#[test]
+fn test_check_executable_in() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let jail = std::env::temp_dir().join(format!("privdrop-exec-{}", std::process::id()));
+    std::fs::create_dir_all(jail.join("bin")).unwrap();
+    std::fs::write(jail.join("bin/prog"), b"").unwrap();
+    std::fs::set_permissions(
+        jail.join("bin/prog"),
+        std::fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+    symlink("/bin/sh", jail.join("bin/escape")).unwrap();
+    symlink("/", jail.join("host")).unwrap();
+    let jail_path = CString::new(jail.as_os_str().as_bytes()).unwrap();
+    let jail_fd = open_dir(libc::AT_FDCWD, &jail_path).unwrap();
+    let check = |workdir: Option<&str>, path: &str| {
+        check_executable_in(
+            jail_fd.as_raw_fd(),
+            workdir.map(Path::new),
+            &CString::new(path).unwrap(),
+        )
+    };
+    assert!(check(None, "/bin/prog").is_ok());
+    assert!(check(None, "bin/prog").is_ok());
+    assert!(check(Some("bin"), "prog").is_ok());
+    assert!(check(Some("bin"), "/bin/prog").is_ok());
+    assert!(check(None, "prog").is_err());
+    assert!(check(None, "/bin/escape").is_err());
+    assert!(check(None, "/host/bin/sh").is_err());
+    assert!(check(Some("host/bin"), "sh").is_err());
+    assert!(check(None, "/bin/../bin/prog").is_err());
+    std::fs::remove_dir_all(&jail).unwrap();
+}
+
+#[cfg(feature = "chroot")]
+#[test]
+fn test_chroot_exec_requires_chroot() {
+    let e = PrivDrop::default()
+        .chroot_exec("/bin/true", &["true"])
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+}
+
+impl PrivDrop {
+    /// Apply the changes, then re-execute the current executable with the given arguments
+    ///
+    /// The new process image starts with an empty environment, and doesn't inherit any memory
+    /// from the privileged phase. This function only returns on error.
+    pub fn reexec_after_drop<S: AsRef<OsStr>>(
+        &self,
+        argv: &[S],
+    ) -> Result<Infallible, PrivDropError> {
+        self.exec_after_drop(current_exe()?, argv)
+    }
+
+    /// Apply the changes, then execute the given program with the given arguments
+    ///
+    /// The program is opened before the changes are applied, so its path is relative to the
+    /// original root directory on platforms supporting `fexecve()`.
+    /// The new process image starts with an empty environment. This function only returns on error.
+    pub fn exec_after_drop<P: AsRef<Path>, S: AsRef<OsStr>>(
+        &self,
+        path: P,
+        argv: &[S],
+    ) -> Result<Infallible, PrivDropError> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid executable path")))?;
+        let argv = to_cstrings(argv)?;
+        let argv_ptrs = to_ptrs(&argv);
+        let envp: [*const libc::c_char; 1] = [std::ptr::null()];
+
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+        {
+            let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+            if fd < 0 {
+                return Err(nix::Error::last().into());
+            }
+            if let Err(e) = self.apply() {
+                unsafe { libc::close(fd) };
+                return Err(e);
+            }
+            unsafe { libc::fexecve(fd, argv_ptrs.as_ptr(), envp.as_ptr()) };
+            let e = nix::Error::last();
+            unsafe { libc::close(fd) };
+            Err(e.into())
+        }
+
+        #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+        {
+            self.apply()?;
+            unsafe { libc::execve(path.as_ptr(), argv_ptrs.as_ptr(), envp.as_ptr()) };
+            Err(nix::Error::last().into())
+        }
+    }
+}
+
+#[cfg(feature = "chroot")]
+impl PrivDrop {
+    /// chroot(), apply the changes, then execute a program located inside the new root directory
+    ///
+    /// `path` is interpreted inside the directory set with `chroot()` or `chroot_fd()`, relative
+    /// to `chroot_workdir()` if it is not absolute. Its existence is checked from the chroot directory before any change is
+    /// made, and it can't contain `..` or symbolic links, which could point outside of it.
+    /// The new process image starts with an empty environment. This function only returns on
+    /// error.
+    pub fn chroot_exec<P: AsRef<Path>, S: AsRef<OsStr>>(
+        &self,
+        path: P,
+        argv: &[S],
+    ) -> Result<Infallible, PrivDropError> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid executable path")))?;
+        let argv = to_cstrings(argv)?;
+        let argv_ptrs = to_ptrs(&argv);
+        let envp: [*const libc::c_char; 1] = [std::ptr::null()];
+
+        let res = Self::preload().and_then(|_| {
+            let start = Instant::now();
+            let resolved = self.resolve()?;
+            let chroot_fd;
+            let root = match (&resolved.chroot, &resolved.chroot_fd) {
+                (Some(chroot), _) => {
+                    chroot_fd = open_dir(libc::AT_FDCWD, chroot)?;
+                    chroot_fd.as_raw_fd()
+                }
+                (None, Some(fd)) => fd.as_raw_fd(),
+                (None, None) => {
+                    return Err(PrivDropError::from((
+                        ErrorKind::InvalidConfig,
+                        "A chroot directory is required to execute a program inside it",
+                    )))
+                }
+            };
+            check_executable_in(root, self.chroot_workdir.as_deref(), &path)?;
+            self.apply_resolved(resolved, start.elapsed(), || Ok(()))
+        });
+        res.map_err(|e| self.explain(e))?;
+        unsafe { libc::execve(path.as_ptr(), argv_ptrs.as_ptr(), envp.as_ptr()) };
+        Err(nix::Error::last().into())
+    }
+}
+
+fn to_cstrings<S: AsRef<OsStr>>(args: &[S]) -> Result<Vec<CString>, PrivDropError> {
+    args.iter()
+        .map(|arg| CString::new(arg.as_ref().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid argument")))
+}
+
+fn to_ptrs(args: &[CString]) -> Vec<*const libc::c_char> {
+    let mut ptrs: Vec<*const libc::c_char> = args.iter().map(|x| x.as_ptr()).collect();
+    ptrs.push(std::ptr::null());
+    ptrs
+}
+
+/// Open a directory relative to `dirfd`, without following symbolic links
+#[cfg(feature = "chroot")]
+fn open_dir(dirfd: RawFd, name: &CStr) -> Result<OwnedFd, PrivDropError> {
+    let fd = unsafe {
+        libc::openat(
+            dirfd,
+            name.as_ptr(),
+            libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        return Err(nix::Error::last().into());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Check that `path` is an executable file inside the directory `root`, as seen after changing
+/// the root directory to it and the working directory to `workdir`
+#[cfg(feature = "chroot")]
+fn check_executable_in(
+    root: RawFd,
+    workdir: Option<&Path>,
+    path: &CStr,
+) -> Result<(), PrivDropError> {
+    let path = Path::new("/")
+        .join(workdir.unwrap_or_else(|| Path::new("")))
+        .join(OsStr::from_bytes(path.to_bytes()));
+    let mut components = vec![];
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurDir => {}
+            Component::Normal(name) => components.push(name),
+            _ => {
+                return Err(PrivDropError::from((
+                    ErrorKind::InvalidConfig,
+                    "The path of the program to execute inside the chroot can't contain '..'",
+                )))
+            }
+        }
+    }
+    let file_name = components.pop().ok_or_else(|| {
+        PrivDropError::from((ErrorKind::InvalidConfig, "Invalid executable path"))
+    })?;
+    let to_cstring = |name: &OsStr| {
+        CString::new(name.as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid executable path")))
+    };
+    // Intermediate directories are opened one by one, so that symbolic links are never followed.
+    let mut dirfd: Option<OwnedFd> = None;
+    for name in components {
+        let parent = dirfd.as_ref().map_or(root, |fd| fd.as_raw_fd());
+        dirfd = Some(open_dir(parent, &to_cstring(name)?)?);
+    }
+    let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+    if unsafe {
+        libc::fstatat(
+            dirfd.as_ref().map_or(root, |fd| fd.as_raw_fd()),
+            to_cstring(file_name)?.as_ptr(),
+            &mut st,
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    } != 0
+    {
+        return Err(nix::Error::last().into());
+    }
+    if st.st_mode & libc::S_IFMT != libc::S_IFREG || st.st_mode & 0o111 == 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The program to execute inside the chroot is not an executable file",
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn current_exe() -> Result<PathBuf, PrivDropError> {
+    Ok(PathBuf::from("/proc/self/exe"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn current_exe() -> Result<PathBuf, PrivDropError> {
+    std::env::current_exe().map_err(|_| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to locate the current executable",
+        ))
+    })
+}
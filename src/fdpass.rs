@@ -0,0 +1,61 @@
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+
+use super::errors::*;
+
+const CMSG_BUF_LEN: usize = 64;
+
+/// Send a file descriptor over a Unix socket
+pub(crate) fn send_fd(socket: RawFd, fd: RawFd) -> Result<(), PrivDropError> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut _,
+        iov_len: byte.len(),
+    };
+    let mut cmsg_buf = [0u64; CMSG_BUF_LEN / 8];
+    let mut msg = unsafe { std::mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr() as *mut _;
+    msg.msg_controllen = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as _) } as _;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as _) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut RawFd, fd);
+    }
+    if unsafe { libc::sendmsg(socket, &msg, 0) } < 0 {
+        return Err(nix::Error::last().into());
+    }
+    Ok(())
+}
+
+/// Receive a file descriptor sent over a Unix socket
+pub(crate) fn recv_fd(socket: RawFd) -> Result<OwnedFd, PrivDropError> {
+    let mut byte = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut _,
+        iov_len: byte.len(),
+    };
+    let mut cmsg_buf = [0u64; CMSG_BUF_LEN / 8];
+    let mut msg = unsafe { std::mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr() as *mut _;
+    msg.msg_controllen = CMSG_BUF_LEN as _;
+    if unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) } < 0 {
+        return Err(nix::Error::last().into());
+    }
+    let cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    if cmsg.is_null()
+        || unsafe { (*cmsg).cmsg_level } != libc::SOL_SOCKET
+        || unsafe { (*cmsg).cmsg_type } != libc::SCM_RIGHTS
+    {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "No file descriptor was received",
+        )));
+    }
+    let fd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const RawFd) };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
@@ -0,0 +1,271 @@
+#[cfg(not(target_os = "android"))]
+use std::ffi::CString;
+use std::ffi::OsStr;
+#[cfg(not(target_os = "android"))]
+use std::os::unix::ffi::OsStrExt;
+
+#[cfg(all(feature = "zeroize", not(target_os = "android")))]
+use zeroize::Zeroize;
+
+#[cfg(target_os = "android")]
+use super::android;
+use super::errors::*;
+use super::privdrop::*;
+use super::resolved::*;
+use super::resolver::*;
+
+#[test]
+fn test_limit_groups() {
+    let groups = vec![10, 20, 30, 40, 100];
+    assert_eq!(
+        limit_groups(
+            groups.clone(),
+            3,
+            Some(100),
+            &[40],
+            GroupLimitPolicy::Truncate
+        ),
+        (vec![100, 10, 20], vec![30, 40])
+    );
+    assert_eq!(
+        limit_groups(
+            groups.clone(),
+            3,
+            Some(100),
+            &[40],
+            GroupLimitPolicy::PreferExplicit
+        ),
+        (vec![100, 40, 10], vec![20, 30])
+    );
+    assert_eq!(
+        limit_groups(groups.clone(), 3, Some(100), &[40], GroupLimitPolicy::Error),
+        (groups, vec![])
+    );
+}
+
+/// Supplementary groups, in order and without duplicates, usable without `PrivDrop`
+///
+/// This is what `PrivDrop` uses to compute the supplementary groups, for services that need
+/// to adjust their groups at runtime without changing their user or root directory.
+///
+/// # Example
+/// ```no_run
+/// # use privdrop::GroupSet;
+/// # fn main() -> Result<(), privdrop::PrivDropError> {
+/// let mut groups = GroupSet::current()?;
+/// groups.insert_name("ssl-cert")?;
+/// groups.apply()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GroupSet {
+    gids: Vec<libc::gid_t>,
+}
+
+impl GroupSet {
+    /// Create an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a set from group IDs, ignoring duplicates
+    pub fn from_ids<I: IntoIterator<Item = libc::gid_t>>(gids: I) -> Self {
+        let mut set = Self::new();
+        set.extend(gids);
+        set
+    }
+
+    /// Supplementary groups of the current process
+    pub fn current() -> Result<Self, PrivDropError> {
+        Ok(Self::from_ids(ResolvedPrivDrop::current_groups()?))
+    }
+
+    /// Default supplementary groups of `user`, as found in the group database, including the
+    /// primary group `gid`
+    #[cfg(target_os = "android")]
+    pub fn for_user<S: AsRef<OsStr>>(user: S, gid: libc::gid_t) -> Result<Self, PrivDropError> {
+        // Bionic's getgrouplist() only ever returns the base group.
+        let username = user
+            .as_ref()
+            .to_str()
+            .ok_or_else(|| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username")))?;
+        Ok(Self::from_ids(android::default_groups(username, gid)))
+    }
+
+    /// Default supplementary groups of `user`, as found in the group database, including the
+    /// primary group `gid`
+    #[cfg(not(target_os = "android"))]
+    pub fn for_user<S: AsRef<OsStr>>(user: S, gid: libc::gid_t) -> Result<Self, PrivDropError> {
+        let username = CString::new(user.as_ref().as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username")))?;
+        let mut groups: Vec<libc::gid_t> = vec![0; 256];
+        loop {
+            let mut ngroups = groups.len() as _;
+            let ret = unsafe {
+                libc::getgrouplist(
+                    username.as_ptr(),
+                    gid as _,
+                    groups.as_mut_ptr() as *mut _,
+                    &mut ngroups,
+                )
+            };
+            if ret >= 0 {
+                groups.truncate(ngroups as _);
+                return Ok(Self::from_ids(groups));
+            }
+            let ngroups = ngroups as usize;
+            let len = if ngroups > groups.len() {
+                ngroups
+            } else if groups.len() < 65536 {
+                groups.len() * 2
+            } else {
+                return Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "Unable to retrieve the default supplementary groups",
+                )));
+            };
+            // Growing the buffer may move it, leaving the partial list behind.
+            #[cfg(feature = "zeroize")]
+            groups.zeroize();
+            groups.resize(len, 0);
+        }
+    }
+
+    /// Maximum number of supplementary groups supported by the system
+    pub fn max_len() -> usize {
+        let ngroups_max = unsafe { libc::sysconf(libc::_SC_NGROUPS_MAX) };
+        if ngroups_max >= 0 {
+            ngroups_max as usize
+        } else {
+            usize::MAX
+        }
+    }
+
+    /// Group IDs, in the order they were added
+    pub fn ids(&self) -> &[libc::gid_t] {
+        &self.gids
+    }
+
+    /// Number of groups
+    pub fn len(&self) -> usize {
+        self.gids.len()
+    }
+
+    /// Return `true` if the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.gids.is_empty()
+    }
+
+    /// Return `true` if the set contains `gid`
+    pub fn contains(&self, gid: libc::gid_t) -> bool {
+        self.gids.contains(&gid)
+    }
+
+    /// Add a group, and return `false` if it was already present
+    pub fn insert(&mut self, gid: libc::gid_t) -> bool {
+        if self.contains(gid) {
+            return false;
+        }
+        self.gids.push(gid);
+        true
+    }
+
+    /// Look up a group by name, add it, and return its ID
+    pub fn insert_name<S: AsRef<OsStr>>(&mut self, group: S) -> Result<libc::gid_t, PrivDropError> {
+        self.insert_name_with(&mut Resolver::new(), group)
+    }
+
+    /// Like `insert_name()`, using the given resolver for the lookup
+    pub fn insert_name_with<S: AsRef<OsStr>>(
+        &mut self,
+        resolver: &mut Resolver,
+        group: S,
+    ) -> Result<libc::gid_t, PrivDropError> {
+        let gid = PrivDrop::lookup_group(resolver, group.as_ref(), None)?;
+        self.insert(gid);
+        Ok(gid)
+    }
+
+    /// Remove a group, and return `true` if it was present
+    pub fn remove(&mut self, gid: libc::gid_t) -> bool {
+        let len = self.gids.len();
+        self.gids.retain(|&group| group != gid);
+        self.gids.len() != len
+    }
+
+    /// Only keep the groups for which `f` returns `true`
+    pub fn retain<F: FnMut(libc::gid_t) -> bool>(&mut self, mut f: F) {
+        self.gids.retain(|&group| f(group));
+    }
+
+    /// Reduce the set to the number of groups supported by the system, according to `policy`,
+    /// and return the groups left out
+    ///
+    /// `gid` is always kept, and, with `GroupLimitPolicy::PreferExplicit`, `explicit_groups`
+    /// are kept before the other ones. Nothing is removed with `GroupLimitPolicy::Error`.
+    pub fn limit(
+        &mut self,
+        gid: Option<libc::gid_t>,
+        explicit_groups: &[libc::gid_t],
+        policy: GroupLimitPolicy,
+    ) -> Vec<libc::gid_t> {
+        let (gids, truncated_groups) = limit_groups(
+            std::mem::take(&mut self.gids),
+            Self::max_len(),
+            gid,
+            explicit_groups,
+            policy,
+        );
+        self.gids = gids;
+        truncated_groups
+    }
+
+    /// Replace the supplementary groups of the process with this set
+    pub fn apply(&self) -> Result<(), PrivDropError> {
+        if self.len() > Self::max_len() {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Too many supplementary groups",
+            )));
+        }
+        ResolvedPrivDrop::setgroups(&self.gids)
+    }
+
+    pub(crate) fn into_ids(self) -> Vec<libc::gid_t> {
+        self.gids
+    }
+}
+
+impl Extend<libc::gid_t> for GroupSet {
+    fn extend<I: IntoIterator<Item = libc::gid_t>>(&mut self, gids: I) {
+        for gid in gids {
+            self.insert(gid);
+        }
+    }
+}
+
+/// Apply a `GroupLimitPolicy`, and return the groups to keep and the groups left out
+fn limit_groups(
+    mut groups: Vec<libc::gid_t>,
+    limit: usize,
+    gid: Option<libc::gid_t>,
+    explicit_groups: &[libc::gid_t],
+    policy: GroupLimitPolicy,
+) -> (Vec<libc::gid_t>, Vec<libc::gid_t>) {
+    if groups.len() <= limit || policy == GroupLimitPolicy::Error {
+        return (groups, vec![]);
+    }
+    // The sort is stable, so groups of the same priority stay in their original order.
+    groups.sort_by_key(|group| {
+        if Some(*group) == gid {
+            0
+        } else if policy == GroupLimitPolicy::PreferExplicit && explicit_groups.contains(group) {
+            1
+        } else {
+            2
+        }
+    });
+    let truncated_groups = groups.split_off(limit);
+    (groups, truncated_groups)
+}
@@ -0,0 +1,327 @@
+use nix::errno::Errno;
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+use nix::unistd;
+
+use super::errors::*;
+use super::privdrop::*;
+
+#[test]
+fn test_detach_tty_as_group_leader() {
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        // A process group leader can't create a new session, so TIOCNOTTY has to be used.
+        let ok = unsafe { libc::setpgid(0, 0) } == 0 && detach_tty().is_ok();
+        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
+
+/// Prevent the process and its children from gaining privileges through execve()
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_no_new_privs() -> Result<(), PrivDropError> {
+    Errno::result(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    Ok(())
+}
+
+/// Prevent the process and its children from gaining privileges through execve()
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_no_new_privs() -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Setting no_new_privs is not supported on this platform",
+    )))
+}
+
+/// Prevent the process from writing core dumps, which could leak privileged data
+pub(crate) fn disable_core_dumps() -> Result<(), PrivDropError> {
+    let rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    Errno::result(unsafe { libc::setrlimit(libc::RLIMIT_CORE, &rlim) })?;
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Errno::result(unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) })?;
+    Ok(())
+}
+
+/// Set the real, effective and saved group IDs
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn setresgid(gid: libc::gid_t) -> Result<(), PrivDropError> {
+    Errno::result(unsafe { libc::setresgid(gid, gid, gid) })?;
+    Ok(())
+}
+
+/// Set the real, effective and saved group IDs
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn setresgid(gid: libc::gid_t) -> Result<(), PrivDropError> {
+    unistd::setgid(unistd::Gid::from_raw(gid))?;
+    Ok(())
+}
+
+/// Set the real, effective and saved user IDs
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+pub(crate) fn setresuid(uid: libc::uid_t) -> Result<(), PrivDropError> {
+    Errno::result(unsafe { libc::setresuid(uid, uid, uid) })?;
+    Ok(())
+}
+
+/// Set the real, effective and saved user IDs
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+)))]
+pub(crate) fn setresuid(uid: libc::uid_t) -> Result<(), PrivDropError> {
+    unistd::setuid(unistd::Uid::from_raw(uid))?;
+    Ok(())
+}
+
+/// Lock the securebits so that root can't regain capabilities by executing programs, and
+/// so that changing the user ID always clears capabilities
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn lock_securebits() -> Result<(), PrivDropError> {
+    const SECBIT_NOROOT: libc::c_ulong = 1 << 0;
+    const SECBIT_NOROOT_LOCKED: libc::c_ulong = 1 << 1;
+    const SECBIT_NO_SETUID_FIXUP_LOCKED: libc::c_ulong = 1 << 3;
+    Errno::result(unsafe {
+        libc::prctl(
+            libc::PR_SET_SECUREBITS,
+            SECBIT_NOROOT | SECBIT_NOROOT_LOCKED | SECBIT_NO_SETUID_FIXUP_LOCKED,
+            0,
+            0,
+            0,
+        )
+    })?;
+    Ok(())
+}
+
+/// Lock the securebits so that root can't regain capabilities by executing programs, and
+/// so that changing the user ID always clears capabilities
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn lock_securebits() -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Securebits are not supported on this platform",
+    )))
+}
+
+/// Disable a speculative execution feature for the process and its future children, in a way
+/// that can't be reverted
+///
+/// Nothing is done if the CPU is not affected, or if the mitigation is already enforced
+/// system-wide.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn disable_speculation(speculation: Speculation) -> Result<(), PrivDropError> {
+    const PR_GET_SPECULATION_CTRL: libc::c_int = 52;
+    const PR_SET_SPECULATION_CTRL: libc::c_int = 53;
+    const PR_SPEC_STORE_BYPASS: libc::c_ulong = 0;
+    const PR_SPEC_INDIRECT_BRANCH: libc::c_ulong = 1;
+    const PR_SPEC_NOT_AFFECTED: libc::c_int = 0;
+    const PR_SPEC_PRCTL: libc::c_int = 1 << 0;
+    const PR_SPEC_DISABLE: libc::c_int = 1 << 2;
+    const PR_SPEC_FORCE_DISABLE: libc::c_ulong = 1 << 3;
+    let which = match speculation {
+        Speculation::StoreBypass => PR_SPEC_STORE_BYPASS,
+        Speculation::IndirectBranch => PR_SPEC_INDIRECT_BRANCH,
+    };
+    let state = Errno::result(unsafe { libc::prctl(PR_GET_SPECULATION_CTRL, which, 0, 0, 0) })?;
+    if state == PR_SPEC_NOT_AFFECTED {
+        return Ok(());
+    }
+    if state & PR_SPEC_PRCTL == 0 {
+        if state & PR_SPEC_DISABLE != 0 {
+            return Ok(());
+        }
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Speculation control is disabled system-wide",
+        )));
+    }
+    Errno::result(unsafe {
+        libc::prctl(PR_SET_SPECULATION_CTRL, which, PR_SPEC_FORCE_DISABLE, 0, 0)
+    })?;
+    Ok(())
+}
+
+/// Disable a speculative execution feature for the process and its future children, in a way
+/// that can't be reverted
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn disable_speculation(_speculation: Speculation) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Speculation control is only supported on Linux",
+    )))
+}
+
+/// Choose which processes can trace the process under Yama's restricted ptrace mode
+///
+/// Changing the user ID makes the process non-dumpable, which prevents unprivileged processes
+/// from tracing it regardless of Yama, so it is made dumpable again if a tracer is allowed.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_ptracer(ptracer: Ptracer) -> Result<(), PrivDropError> {
+    let arg = match ptracer {
+        Ptracer::Restricted => 0,
+        Ptracer::Process(pid) => pid as libc::c_ulong,
+        Ptracer::Any => libc::PR_SET_PTRACER_ANY,
+    };
+    if ptracer != Ptracer::Restricted {
+        Errno::result(unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 1, 0, 0, 0) })?;
+    }
+    match Errno::result(unsafe { libc::prctl(libc::PR_SET_PTRACER, arg, 0, 0, 0) }) {
+        Ok(_) => Ok(()),
+        Err(Errno::EINVAL) => match ptracer {
+            Ptracer::Process(pid)
+                if unsafe { libc::kill(pid, 0) } != 0 && Errno::last() == Errno::ESRCH =>
+            {
+                Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "The process allowed to trace the process doesn't exist",
+                )))
+            }
+            // Yama is not enabled, so there are no restrictions to change.
+            _ => Ok(()),
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Choose which processes can trace the process under Yama's restricted ptrace mode
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_ptracer(_ptracer: Ptracer) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Setting a ptracer is only supported on Linux",
+    )))
+}
+
+/// Remove all the environment variables
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+pub(crate) fn clear_env() -> Result<(), PrivDropError> {
+    Errno::result(unsafe { libc::clearenv() })?;
+    Ok(())
+}
+
+/// Remove all the environment variables
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+pub(crate) fn clear_env() -> Result<(), PrivDropError> {
+    for (key, _) in std::env::vars_os() {
+        std::env::remove_var(key);
+    }
+    Ok(())
+}
+
+/// Set the close-on-exec flag on all file descriptors except the standard ones
+pub(crate) fn set_cloexec_on_fds() -> Result<(), PrivDropError> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_close_range,
+                3 as libc::c_uint,
+                libc::c_uint::MAX,
+                CLOSE_RANGE_CLOEXEC,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+    }
+    let max_fd = match unsafe { libc::sysconf(libc::_SC_OPEN_MAX) } {
+        max_fd if max_fd > 3 => max_fd as libc::c_int,
+        _ => 1024,
+    };
+    for fd in 3..max_fd {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags >= 0 && flags & libc::FD_CLOEXEC == 0 {
+            Errno::result(unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) })?;
+        }
+    }
+    Ok(())
+}
+
+/// Detach the process from its controlling terminal
+///
+/// A new session is created if possible. Otherwise, as a process group leader can't create
+/// a new session, the terminal is released with `TIOCNOTTY`. This requires `/dev/tty`, so it
+/// has to be done before the root directory is changed.
+pub(crate) fn detach_tty() -> Result<(), PrivDropError> {
+    if unsafe { libc::setsid() } >= 0 {
+        return Ok(());
+    }
+    let fd = unsafe {
+        libc::open(
+            b"/dev/tty\0".as_ptr() as *const _,
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        )
+    };
+    if fd < 0 {
+        // ENXIO means that there is no controlling terminal to detach from.
+        if Errno::last() == Errno::ENXIO {
+            return Ok(());
+        }
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to open the controlling terminal in order to detach from it",
+        )));
+    }
+    let ret = unsafe { libc::ioctl(fd, libc::TIOCNOTTY as _) };
+    let e = nix::Error::last();
+    unsafe { libc::close(fd) };
+    if ret != 0 {
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Prevent the process from being traced or dumped, even by processes running as the same user
+#[cfg(target_os = "freebsd")]
+pub(crate) fn disable_tracing() -> Result<(), PrivDropError> {
+    let mut arg: libc::c_int = libc::PROC_TRACE_CTL_DISABLE;
+    Errno::result(unsafe {
+        libc::procctl(
+            libc::P_PID,
+            libc::getpid() as libc::id_t,
+            libc::PROC_TRACE_CTL,
+            &mut arg as *mut libc::c_int as *mut libc::c_void,
+        )
+    })?;
+    Ok(())
+}
+
+/// Prevent the process from being traced or dumped, even by processes running as the same user
+#[cfg(not(target_os = "freebsd"))]
+pub(crate) fn disable_tracing() -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Disabling tracing is only supported on FreeBSD",
+    )))
+}
@@ -0,0 +1,105 @@
+use nix::unistd;
+
+use super::errors::*;
+use super::hardening;
+
+/// A change of the user and group IDs only, usable without `PrivDrop`
+///
+/// Supplementary groups and the root directory are left untouched. This is what `PrivDrop`
+/// uses to change the IDs once everything else is in place.
+///
+/// # Example
+/// ```no_run
+/// # use privdrop::IdentitySwitch;
+/// # fn main() -> Result<(), privdrop::PrivDropError> {
+/// IdentitySwitch::new().gid(65534).uid(65534).clear_saved_ids().apply()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IdentitySwitch {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    clear_saved_ids: bool,
+}
+
+impl IdentitySwitch {
+    /// Create a switch that doesn't change anything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Switch to the user ID `uid`
+    pub fn uid(mut self, uid: libc::uid_t) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Switch to the group ID `gid`
+    pub fn gid(mut self, gid: libc::gid_t) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Also reset the saved user and group IDs, so that they can't be restored later
+    pub fn clear_saved_ids(mut self) -> Self {
+        self.clear_saved_ids = true;
+        self
+    }
+
+    /// Change the group ID, then the user ID, and verify the result
+    ///
+    /// The effective IDs must match the requested ones, and a process that was running as root
+    /// must not be able to switch back to root afterwards.
+    pub fn apply(&self) -> Result<(), PrivDropError> {
+        let was_root = unistd::geteuid().is_root();
+        if let Some(gid) = self.gid {
+            set_gid(gid, self.clear_saved_ids)?;
+        }
+        if let Some(uid) = self.uid {
+            set_uid(uid, self.clear_saved_ids)?;
+        }
+        if self
+            .gid
+            .is_some_and(|gid| unistd::getegid().as_raw() != gid)
+            || self
+                .uid
+                .is_some_and(|uid| unistd::geteuid().as_raw() != uid)
+        {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "The effective IDs don't match the requested IDs",
+            )));
+        }
+        if was_root
+            && !unistd::geteuid().is_root()
+            && unistd::setuid(unistd::Uid::from_raw(0)).is_ok()
+        {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Root privileges can still be regained",
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Change the group ID, including the saved one if `clear_saved_ids` is set
+pub(crate) fn set_gid(gid: libc::gid_t, clear_saved_ids: bool) -> Result<(), PrivDropError> {
+    if clear_saved_ids {
+        hardening::setresgid(gid)
+    } else {
+        unistd::setgid(unistd::Gid::from_raw(gid))?;
+        Ok(())
+    }
+}
+
+/// Change the user ID, including the saved one if `clear_saved_ids` is set
+pub(crate) fn set_uid(uid: libc::uid_t, clear_saved_ids: bool) -> Result<(), PrivDropError> {
+    if clear_saved_ids {
+        hardening::setresuid(uid)
+    } else {
+        unistd::setuid(unistd::Uid::from_raw(uid))?;
+        Ok(())
+    }
+}
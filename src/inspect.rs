@@ -0,0 +1,258 @@
+#[cfg(feature = "chroot")]
+use std::ffi::OsStr;
+#[cfg(feature = "chroot")]
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use super::errors::*;
+#[cfg(all(test, feature = "chroot"))]
+use super::privdrop::*;
+use super::resolved::*;
+
+#[test]
+fn test_parse_status() {
+    let status = "Name:\tworker\nUmask:\t0077\nUid:\t65534\t65534\t65534\t65534\n\
+                  Gid:\t100\t100\t100\t100\nFDSize:\t64\nGroups:\t100 65534 \n\
+                  CapInh:\t0000000000000000\nCapPrm:\t0000000000000400\n\
+                  CapEff:\t0000000000000400\nCapAmb:\t0000000000000000\nNoNewPrivs:\t1\n";
+    let creds = ProcessCredentials::parse(status, Some(PathBuf::from("/var/empty"))).unwrap();
+    assert_eq!(creds.uids, [65534; 4]);
+    assert_eq!(creds.gids, [100; 4]);
+    assert_eq!(creds.groups, vec![100, 65534]);
+    assert_eq!(creds.root, Some(PathBuf::from("/var/empty")));
+    assert_eq!((creds.cap_permitted, creds.cap_effective), (0x400, 0x400));
+    assert_eq!(creds.cap_ambient, 0);
+    assert!(creds.no_new_privs);
+    assert!(ProcessCredentials::parse("Uid:\t0\t0\n", None).is_err());
+    assert!(ProcessCredentials::parse("Groups:\tx\n", None).is_err());
+    assert_eq!(parse_ids::<2>("1 2 3"), Some([1, 2]));
+    assert_eq!(parse_ids::<2>("1"), None);
+}
+
+#[test]
+fn test_mismatches() {
+    let expected = ResolvedPrivDrop::deserialize("uid=65534;gid=65534;groups=100,65534").unwrap();
+    let mut creds = ProcessCredentials {
+        uids: [65534; 4],
+        gids: [65534; 4],
+        groups: vec![65534, 100],
+        root: Some(PathBuf::from("/")),
+        ..Default::default()
+    };
+    assert!(expected.mismatches(&creds).is_empty());
+    creds.uids[2] = 0;
+    creds.cap_permitted = 1;
+    assert_eq!(expected.mismatches(&creds).len(), 2);
+}
+
+#[cfg(feature = "chroot")]
+#[test]
+fn test_root_mismatches() {
+    let expected = ResolvedPrivDrop::deserialize("chroot=2f7661722f656d707479").unwrap();
+    let mut creds = ProcessCredentials::default();
+    assert_eq!(
+        expected.mismatches(&creds),
+        vec!["the root directory can't be read"]
+    );
+    creds.root = Some(PathBuf::from("/tmp"));
+    assert_eq!(
+        expected.mismatches(&creds),
+        vec!["the root directory is /tmp, expected /var/empty"]
+    );
+    creds.root = Some(PathBuf::from("/var/empty"));
+    assert!(expected.mismatches(&creds).is_empty());
+
+    let root = std::os::unix::io::OwnedFd::from(std::fs::File::open("/").unwrap());
+    let expected = PrivDrop::default().chroot_fd(root).resolve().unwrap();
+    creds.root = Some(PathBuf::from("/"));
+    assert_eq!(
+        expected.mismatches(&creds),
+        vec!["the root directory was not changed"]
+    );
+    creds.root = Some(PathBuf::from("/var/empty"));
+    assert!(expected.mismatches(&creds).is_empty());
+}
+
+#[cfg(all(feature = "caps", target_os = "linux"))]
+#[test]
+fn test_mismatches_with_retained_caps() {
+    let expected =
+        ResolvedPrivDrop::deserialize("uid=65534;gid=65534;groups=65534;caps=CAP_NET_BIND_SERVICE")
+            .unwrap();
+    let creds = ProcessCredentials {
+        uids: [65534, 65534, 0, 0],
+        gids: [65534; 4],
+        groups: vec![65534],
+        root: Some(PathBuf::from("/")),
+        cap_permitted: 0x400,
+        cap_effective: 0x400,
+        ..Default::default()
+    };
+    assert!(expected.mismatches(&creds).is_empty());
+}
+
+/// Credentials of a running process, as reported by the kernel in `/proc`
+///
+/// This doesn't rely on anything the process says about itself, so it can be used to confirm
+/// from the outside that the privileges were dropped.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessCredentials {
+    /// Real, effective, saved and filesystem user IDs
+    pub uids: [libc::uid_t; 4],
+    /// Real, effective, saved and filesystem group IDs
+    pub gids: [libc::gid_t; 4],
+    /// Supplementary groups
+    pub groups: Vec<libc::gid_t>,
+    /// Root directory, if it could be read
+    pub root: Option<PathBuf>,
+    /// Permitted capabilities
+    pub cap_permitted: u64,
+    /// Effective capabilities
+    pub cap_effective: u64,
+    /// Ambient capabilities
+    pub cap_ambient: u64,
+    /// Whether the no_new_privs flag is set
+    pub no_new_privs: bool,
+}
+
+fn parse_ids<const N: usize>(value: &str) -> Option<[u32; N]> {
+    let mut ids = [0; N];
+    let mut values = value.split_whitespace();
+    for id in ids.iter_mut() {
+        *id = values.next()?.parse().ok()?;
+    }
+    Some(ids)
+}
+
+impl ProcessCredentials {
+    /// Read the credentials of the process `pid`
+    ///
+    /// Reading the root directory of a process running as another user requires privileges.
+    pub fn inspect(pid: libc::pid_t) -> Result<Self, PrivDropError> {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).map_err(|_| {
+            PrivDropError::from((ErrorKind::SysError, "Unable to read the process status"))
+        })?;
+        let root = std::fs::read_link(format!("/proc/{}/root", pid)).ok();
+        Self::parse(&status, root)
+    }
+
+    /// Parse the content of `/proc/<pid>/status`
+    fn parse(status: &str, root: Option<PathBuf>) -> Result<Self, PrivDropError> {
+        let invalid = || {
+            PrivDropError::from((
+                ErrorKind::SysError,
+                "Unexpected format of the process status",
+            ))
+        };
+        let mut creds = ProcessCredentials {
+            root,
+            ..Default::default()
+        };
+        for line in status.lines() {
+            let (key, value) = match line.split_once(':') {
+                Some(field) => field,
+                None => continue,
+            };
+            let value = value.trim();
+            match key {
+                "Uid" => creds.uids = parse_ids(value).ok_or_else(invalid)?,
+                "Gid" => creds.gids = parse_ids(value).ok_or_else(invalid)?,
+                "Groups" => {
+                    for group in value.split_whitespace() {
+                        creds.groups.push(group.parse().map_err(|_| invalid())?);
+                    }
+                }
+                "CapPrm" | "CapEff" | "CapAmb" => {
+                    let caps = u64::from_str_radix(value, 16).map_err(|_| invalid())?;
+                    match key {
+                        "CapPrm" => creds.cap_permitted = caps,
+                        "CapEff" => creds.cap_effective = caps,
+                        _ => creds.cap_ambient = caps,
+                    }
+                }
+                "NoNewPrivs" => creds.no_new_privs = value == "1",
+                _ => {}
+            }
+        }
+        Ok(creds)
+    }
+}
+
+impl ResolvedPrivDrop {
+    /// Compare the changes with the credentials of a running process, and describe the
+    /// differences
+    ///
+    /// An empty list means that the process matches. Groups are not compared when they were
+    /// set by `initgroups()`. When capabilities were retained, they are not compared, and
+    /// neither are the saved and filesystem IDs, which may differ by design.
+    pub fn mismatches(&self, creds: &ProcessCredentials) -> Vec<String> {
+        let mut mismatches = vec![];
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        let keeps_caps = !self.keep_caps.is_empty();
+        #[cfg(not(all(feature = "caps", target_os = "linux")))]
+        let keeps_caps = false;
+        // Real and effective IDs, followed by the saved and filesystem IDs
+        let compared_ids = if keeps_caps { 2 } else { 4 };
+        if let Some(uid) = self.uid {
+            if creds.uids[..compared_ids].iter().any(|&x| x != uid) {
+                mismatches.push(format!("user IDs are {:?}, expected {}", creds.uids, uid));
+            }
+        }
+        if let Some(gid) = self.gid {
+            if creds.gids[..compared_ids].iter().any(|&x| x != gid) {
+                mismatches.push(format!("group IDs are {:?}, expected {}", creds.gids, gid));
+            }
+        }
+        #[cfg(feature = "groups")]
+        let initgroups = self.initgroups_user.is_some();
+        #[cfg(not(feature = "groups"))]
+        let initgroups = false;
+        if self.gid.is_some() && !self.skip_setgroups && !initgroups {
+            let mut groups = creds.groups.clone();
+            let mut expected = self.groups.clone();
+            groups.sort_unstable();
+            expected.sort_unstable();
+            if groups != expected {
+                mismatches.push(format!(
+                    "supplementary groups are {:?}, expected {:?}",
+                    groups, expected
+                ));
+            }
+        }
+        #[cfg(feature = "chroot")]
+        let root = self
+            .chroot
+            .as_ref()
+            .map(|chroot| PathBuf::from(OsStr::from_bytes(chroot.as_bytes())));
+        #[cfg(not(feature = "chroot"))]
+        let root: Option<PathBuf> = None;
+        #[cfg(feature = "chroot")]
+        let confined = self.has_chroot();
+        #[cfg(not(feature = "chroot"))]
+        let confined = false;
+        match (&creds.root, root) {
+            (None, _) if confined => {
+                mismatches.push("the root directory can't be read".to_string())
+            }
+            (Some(actual), Some(expected)) if *actual != expected => mismatches.push(format!(
+                "the root directory is {}, expected {}",
+                actual.display(),
+                expected.display()
+            )),
+            (Some(actual), None) if confined && actual.as_os_str() == "/" => {
+                mismatches.push("the root directory was not changed".to_string())
+            }
+            _ => {}
+        }
+        if self.uid.is_some_and(|uid| uid != 0)
+            && !keeps_caps
+            && (creds.cap_permitted | creds.cap_effective | creds.cap_ambient) != 0
+        {
+            mismatches.push("the process still has capabilities".to_string());
+        }
+        if self.no_new_privs && !creds.no_new_privs {
+            mismatches.push("no_new_privs is not set".to_string());
+        }
+        mismatches
+    }
+}
@@ -1,9 +1,100 @@
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub use self::broker::*;
+pub use self::cache::*;
+#[cfg(feature = "chroot")]
+pub use self::chroot::*;
+pub use self::ephemeral::EphemeralLock;
 pub use self::errors::*;
+#[cfg(feature = "groups")]
+pub use self::groups::*;
+pub use self::identity::*;
+#[cfg(target_os = "linux")]
+pub use self::inspect::*;
+#[cfg(feature = "async")]
+pub use self::nonblocking::*;
+pub use self::once::*;
+pub use self::pipeline::*;
+pub use self::prefork::*;
 pub use self::privdrop::*;
+pub use self::raw_sockets::*;
+pub use self::report::*;
+pub use self::resolved::*;
+pub use self::resolver::*;
+pub use self::restrictions::*;
+pub use self::run::*;
+pub use self::tls::*;
+#[cfg(target_os = "linux")]
+pub use self::watches::*;
+pub use self::worker::*;
+#[cfg(feature = "macros")]
+pub use privdrop_macros::main;
 
+mod account;
+#[cfg(target_os = "android")]
+mod android;
+mod audit;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod broker;
+mod cache;
+#[cfg(all(feature = "caps", target_os = "linux"))]
+mod capabilities;
+#[cfg(feature = "chroot")]
+mod chroot;
+mod diagnostics;
+mod dirs;
+mod ephemeral;
 mod errors;
+mod exec;
+#[cfg(target_os = "linux")]
+mod fdpass;
+#[cfg(feature = "groups")]
+mod groups;
+mod hardening;
+mod identity;
+#[cfg(target_os = "linux")]
+mod inspect;
+mod listener;
+#[doc(hidden)]
+pub mod macros;
+#[cfg(any(feature = "chroot", target_os = "linux", target_os = "android"))]
+mod mounts;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod once;
+mod peer;
+mod pipeline;
+mod prefork;
 mod privdrop;
+mod raw_sockets;
+mod report;
+mod resolved;
+mod resolver;
+mod resources;
+mod restrictions;
+mod run;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod seccomp;
+mod systemd;
+mod timeout;
+mod tls;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod userns;
+#[cfg(target_os = "linux")]
+mod watches;
+mod worker;
 
+/// Dependencies, re-exported along with the features using them
 pub mod reexports {
-    pub use {libc, nix};
+    pub use libc;
+    #[cfg(any(feature = "chroot", feature = "groups"))]
+    pub use nix;
 }
@@ -0,0 +1,73 @@
+use std::net::{TcpListener, ToSocketAddrs};
+
+use nix::errno::Errno;
+
+use super::errors::*;
+use super::privdrop::*;
+
+fn bind<A: ToSocketAddrs>(addr: A) -> Result<TcpListener, PrivDropError> {
+    TcpListener::bind(addr).map_err(|e| match e.raw_os_error() {
+        Some(errno) => Errno::from_raw(errno).into(),
+        None => PrivDropError::from((ErrorKind::InvalidConfig, "Invalid listening address")),
+    })
+}
+
+impl PrivDrop {
+    /// Bind a TCP listener with the initial privileges, e.g. on a port below 1024, then apply
+    /// the changes
+    ///
+    /// This is `apply_with_prefetch()` with a bind as the prefetch step. The listener is
+    /// returned in blocking mode.
+    pub fn bind_tcp_listener<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<TcpListener, PrivDropError> {
+        let res = Self::preload().and_then(|_| {
+            let start = std::time::Instant::now();
+            let resolved = self.resolve()?;
+            self.apply_resolved(resolved, start.elapsed(), || bind(addr))
+        });
+        res.map(|(_, listener)| listener)
+            .map_err(|e| self.explain(e))
+    }
+
+    /// Like `bind_tcp_listener()`, but return a Tokio listener, as expected by `axum::serve()`
+    /// and by hyper's server loops
+    ///
+    /// This must be called from within a Tokio runtime.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use privdrop::PrivDrop;
+    /// # async fn serve() -> Result<(), Box<dyn std::error::Error>> {
+    /// let listener = PrivDrop::default()
+    ///     .chroot("/var/empty")
+    ///     .user("www")
+    ///     .bind_tokio_listener("0.0.0.0:443")?;
+    /// let (stream, peer) = listener.accept().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub fn bind_tokio_listener<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<tokio::net::TcpListener, PrivDropError> {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "A Tokio listener can only be created from within a Tokio runtime",
+            )));
+        }
+        let listener = self.bind_tcp_listener(addr)?;
+        let to_error = |e: std::io::Error| match e.raw_os_error() {
+            Some(errno) => PrivDropError::from(Errno::from_raw(errno)),
+            None => PrivDropError::from((
+                ErrorKind::SysError,
+                "Unable to register the listener with the Tokio runtime",
+            )),
+        };
+        listener.set_nonblocking(true).map_err(to_error)?;
+        tokio::net::TcpListener::from_std(listener).map_err(to_error)
+    }
+}
@@ -0,0 +1,85 @@
+use super::privdrop::OPTION_CONFLICTS;
+
+#[test]
+fn test_check_options() {
+    check_options(&["user", "group", "chroot"]);
+    for (a, b, message) in CONFLICTS.iter() {
+        let e = std::panic::catch_unwind(|| check_options(&[a, "no_new_privs", b])).unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<String>().map(|x| x.as_str()),
+            Some(*message)
+        );
+        assert!(message.contains(a) && message.contains(b));
+    }
+}
+
+/// Build a `PrivDrop` from a list of options
+///
+/// `name: value` calls the builder method with the same name, `name(a, b)` passes several
+/// arguments, and a bare `name` calls a method that doesn't take any.
+///
+/// Unknown options, and combinations that can never be applied (e.g. `user` with
+/// `ephemeral_ids`), are rejected at compile time.
+///
+/// # Example
+/// ```no_run
+/// privdrop::privdrop! {
+///     chroot: "/var/empty",
+///     user: "nobody",
+///     runtime_dir("/run/service", 0o700),
+///     no_new_privs,
+/// }
+/// .apply()
+/// .unwrap_or_else(|e| { panic!("Failed to drop privileges: {}", e) });
+/// ```
+#[macro_export]
+macro_rules! privdrop {
+    ($($option:ident $(($($args:expr),* $(,)?))? $(: $value:expr)?),* $(,)?) => {{
+        const _: () = $crate::macros::check_options(&[$(stringify!($option)),*]);
+        let privdrop = $crate::PrivDrop::default();
+        $(let privdrop: $crate::PrivDrop = privdrop.$option($($($args),*)? $($value)?);)*
+        privdrop
+    }};
+}
+
+/// Options that can't be combined, shared with the checks performed when resolving
+const CONFLICTS: &[(&str, &str, &str)] = &OPTION_CONFLICTS;
+
+const fn eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn contains(options: &[&str], option: &str) -> bool {
+    let mut i = 0;
+    while i < options.len() {
+        if eq(options[i], option) {
+            return true;
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Reject combinations of options that can never be applied
+#[doc(hidden)]
+pub const fn check_options(options: &[&str]) {
+    let mut i = 0;
+    while i < CONFLICTS.len() {
+        let (a, b, message) = CONFLICTS[i];
+        if contains(options, a) && contains(options, b) {
+            panic!("{}", message);
+        }
+        i += 1;
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(feature = "chroot")]
+use std::ffi::CStr;
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+use std::ffi::{CString, OsStr};
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+use std::path::Path;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use nix::errno::Errno;
+
+use super::errors::*;
+use super::privdrop::*;
+
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+#[test]
+fn test_failed_overlay_unmounts_tmpfs() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = std::env::temp_dir().join(format!("privdrop-overlay-{}", std::process::id()));
+    std::fs::create_dir(&dir).unwrap();
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        let ok = (|| {
+            if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+                // Mount namespaces require privileges
+                return true;
+            }
+            isolate_mounts(MountPropagation::Private).unwrap();
+            let chroot = CString::new(dir.as_os_str().as_bytes()).unwrap();
+            let dev = std::fs::metadata(&dir).unwrap().dev();
+            let mounted = unsafe {
+                libc::mount(
+                    b"tmpfs\0".as_ptr() as *const _,
+                    chroot.as_ptr(),
+                    b"tmpfs\0".as_ptr() as *const _,
+                    0,
+                    std::ptr::null(),
+                )
+            };
+            // An existing upper directory makes the overlay setup fail after the tmpfs mount
+            if mounted != 0 || std::fs::create_dir(dir.join("upper")).is_err() {
+                return false;
+            }
+            mount_overlay(&chroot, &dir, -1).is_err()
+                && std::fs::metadata(&dir).unwrap().dev() == dev
+        })();
+        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    std::fs::remove_dir(&dir).unwrap();
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
+
+/// Change the propagation of all the mounts of the current mount namespace, so that mounts
+/// performed afterwards are never propagated to the parent namespace
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn isolate_mounts(propagation: MountPropagation) -> Result<(), PrivDropError> {
+    let flag = match propagation {
+        MountPropagation::Private => libc::MS_PRIVATE,
+        MountPropagation::Slave => libc::MS_SLAVE,
+    };
+    Errno::result(unsafe {
+        libc::mount(
+            std::ptr::null(),
+            b"/\0".as_ptr() as *const _,
+            std::ptr::null(),
+            libc::MS_REC | flag,
+            std::ptr::null(),
+        )
+    })?;
+    Ok(())
+}
+
+/// Move the process to a new mount namespace, and replace the chroot directory with an
+/// overlay of itself over an empty tmpfs, so that changes are discarded along with the namespace
+///
+/// The lower layer is reached through a descriptor, as it is hidden by the tmpfs holding the
+/// upper layer once that one is mounted.
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+pub(crate) fn mount_chroot_overlay(
+    chroot: &CStr,
+    propagation: MountPropagation,
+) -> Result<(), PrivDropError> {
+    let path = Path::new(OsStr::from_bytes(chroot.to_bytes()));
+    if chroot
+        .to_bytes()
+        .iter()
+        .any(|c| matches!(c, b',' | b':' | b'\\'))
+    {
+        return Err(PrivDropError::from((
+            ErrorKind::InvalidConfig,
+            "The chroot path can't be used as an overlay layer",
+        )));
+    }
+    Errno::result(unsafe { libc::unshare(libc::CLONE_NEWNS) })?;
+    isolate_mounts(propagation)?;
+    // The descriptor must be opened in the new namespace, to refer to its copy of the mount.
+    let lower_fd = Errno::result(unsafe {
+        libc::open(
+            chroot.as_ptr(),
+            libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+        )
+    })?;
+    let res = Errno::result(unsafe {
+        libc::mount(
+            b"tmpfs\0".as_ptr() as *const _,
+            chroot.as_ptr(),
+            b"tmpfs\0".as_ptr() as *const _,
+            libc::MS_NOSUID | libc::MS_NODEV,
+            b"mode=0755\0".as_ptr() as *const _,
+        )
+    })
+    .map_err(PrivDropError::from)
+    .and_then(|_| mount_overlay(chroot, path, lower_fd));
+    unsafe { libc::close(lower_fd) };
+    res
+}
+
+/// Create the upper layer in the tmpfs mounted over the chroot directory, and mount the
+/// overlay on top of it. The tmpfs is unmounted if this fails.
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+fn mount_overlay(chroot: &CStr, path: &Path, lower_fd: libc::c_int) -> Result<(), PrivDropError> {
+    let res = (|| -> Result<(), PrivDropError> {
+        let (upper, work) = (path.join("upper"), path.join("work"));
+        for dir in [&upper, &work] {
+            std::fs::create_dir(dir).map_err(|_| {
+                PrivDropError::from((
+                    ErrorKind::SysError,
+                    "Unable to create the upper layer of the overlay",
+                ))
+            })?;
+        }
+        let options = CString::new(
+            [
+                format!("lowerdir=/proc/self/fd/{},upperdir=", lower_fd).as_bytes(),
+                upper.as_os_str().as_bytes(),
+                b",workdir=",
+                work.as_os_str().as_bytes(),
+            ]
+            .concat(),
+        )
+        .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid chroot path")))?;
+        Errno::result(unsafe {
+            libc::mount(
+                b"overlay\0".as_ptr() as *const _,
+                chroot.as_ptr(),
+                b"overlay\0".as_ptr() as *const _,
+                0,
+                options.as_ptr() as *const _,
+            )
+        })?;
+        Ok(())
+    })();
+    if res.is_err() {
+        unsafe { libc::umount2(chroot.as_ptr(), libc::MNT_DETACH) };
+    }
+    res
+}
+
+/// Move the process to a new mount namespace, and replace the chroot directory with an
+/// overlay of itself over an empty tmpfs, so that changes are discarded along with the namespace
+#[cfg(all(feature = "chroot", not(target_os = "linux")))]
+pub(crate) fn mount_chroot_overlay(
+    _chroot: &CStr,
+    _propagation: MountPropagation,
+) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Overlay chroot directories are only supported on Linux",
+    )))
+}
@@ -0,0 +1,66 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use super::errors::*;
+use super::privdrop::*;
+use super::resolved::*;
+
+#[derive(Debug, Default)]
+struct ResolveState {
+    result: Option<Result<ResolvedPrivDrop, PrivDropError>>,
+    waker: Option<Waker>,
+}
+
+/// Future returned by `PrivDrop::resolve_async()`
+#[derive(Debug)]
+pub struct ResolveFuture {
+    state: Arc<Mutex<ResolveState>>,
+}
+
+impl PrivDrop {
+    /// Like `resolve()`, but perform the blocking name lookups on a separate thread, so that
+    /// they don't stall the executor of an async runtime
+    ///
+    /// This doesn't depend on any specific runtime. The returned `ResolvedPrivDrop` can then
+    /// be applied synchronously.
+    pub fn resolve_async(&self) -> ResolveFuture {
+        let state = Arc::new(Mutex::new(ResolveState::default()));
+        let config = self.clone();
+        let thread_state = state.clone();
+        let spawned = std::thread::Builder::new()
+            .name("privdrop-resolver".to_string())
+            .spawn(move || {
+                let result = config.resolve();
+                let mut state = thread_state.lock().unwrap_or_else(|e| e.into_inner());
+                state.result = Some(result);
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            });
+        if spawned.is_err() {
+            state.lock().unwrap_or_else(|e| e.into_inner()).result =
+                Some(Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "Unable to start a thread to resolve the credentials",
+                ))));
+        }
+        ResolveFuture { state }
+    }
+}
+
+impl Future for ResolveFuture {
+    type Output = Result<ResolvedPrivDrop, PrivDropError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
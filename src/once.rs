@@ -0,0 +1,64 @@
+use std::sync::{Mutex, OnceLock};
+
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+#[cfg(test)]
+use super::resolved::*;
+
+static APPLIED: OnceLock<(PrivDrop, AppliedPrivileges)> = OnceLock::new();
+
+/// Serializes attempts, so that a failed attempt can be retried
+static APPLYING: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_failed_attempt_can_be_retried() {
+    let config = PrivDrop::default().user("__privdrop_nonexistent_user__");
+    assert!(init_once(&config).is_err());
+    assert!(APPLIED.get().is_none());
+    assert!(init_once(&config).is_err());
+}
+
+#[test]
+fn test_previous_report() {
+    let config = PrivDrop::default().user("nobody");
+    let report =
+        AppliedPrivileges::current(&ResolvedPrivDrop::default(), PhaseTimings::default()).unwrap();
+    let applied = (config.clone(), report.clone());
+    assert_eq!(previous_report(&applied, &config).unwrap(), report);
+    let other = PrivDrop::default().user("daemon");
+    let e = previous_report(&applied, &other).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+}
+
+fn previous_report(
+    applied: &(PrivDrop, AppliedPrivileges),
+    config: &PrivDrop,
+) -> Result<AppliedPrivileges, PrivDropError> {
+    let (applied_config, report) = applied;
+    if applied_config != config {
+        return Err(PrivDropError::from((
+            ErrorKind::InvalidConfig,
+            "Privileges have already been dropped using a different configuration",
+        )));
+    }
+    Ok(report.clone())
+}
+
+/// Apply a configuration exactly once for the whole process
+///
+/// The first successful call applies the changes. Subsequent calls with the same
+/// configuration don't do anything, and return the report of the initial application.
+/// Calling this function with a different configuration returns an `InvalidConfig` error.
+pub fn init_once(config: &PrivDrop) -> Result<AppliedPrivileges, PrivDropError> {
+    if let Some(applied) = APPLIED.get() {
+        return previous_report(applied, config);
+    }
+    let _applying = APPLYING.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(applied) = APPLIED.get() {
+        return previous_report(applied, config);
+    }
+    let report = config.apply_with_report()?;
+    let _ = APPLIED.set((config.clone(), report.clone()));
+    Ok(report)
+}
@@ -0,0 +1,67 @@
+use std::os::unix::io::AsRawFd;
+
+use super::errors::*;
+use super::privdrop::*;
+#[cfg(feature = "groups")]
+use super::resolver::*;
+
+#[test]
+fn test_from_peer() {
+    let (a, _b) = std::os::unix::net::UnixStream::pair().unwrap();
+    let (uid, gid) = peer_ids(a.as_raw_fd()).unwrap();
+    let config = PrivDrop::from_peer(&a).unwrap();
+    assert_eq!(config.user, Some(uid.to_string().into()));
+    assert_eq!(config.group, Some(gid.to_string().into()));
+    assert!(config.fallback_to_ids_if_names_are_numeric);
+    assert_eq!(config.numeric_name_policy, NumericNamePolicy::PreferId);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_ids(fd: libc::c_int) -> Result<(libc::uid_t, libc::gid_t), PrivDropError> {
+    let mut cred = unsafe { std::mem::zeroed::<libc::ucred>() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    if unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut _ as *mut _,
+            &mut len,
+        )
+    } != 0
+    {
+        return Err(nix::Error::last().into());
+    }
+    Ok((cred.uid, cred.gid))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_ids(fd: libc::c_int) -> Result<(libc::uid_t, libc::gid_t), PrivDropError> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(fd, &mut uid, &mut gid) } != 0 {
+        return Err(nix::Error::last().into());
+    }
+    Ok((uid, gid))
+}
+
+impl PrivDrop {
+    /// Configuration switching to the credentials of the process connected to a Unix socket
+    ///
+    /// The peer's user ID and group ID are used as numbers, even if an account is named after
+    /// them. If the user ID has an account, the default supplementary groups of that account
+    /// are also included.
+    pub fn from_peer<S: AsRawFd>(socket: &S) -> Result<Self, PrivDropError> {
+        let (uid, gid) = peer_ids(socket.as_raw_fd())?;
+        let config = PrivDrop::default()
+            .user(uid.to_string())
+            .group(gid.to_string())
+            .fallback_to_ids_if_names_are_numeric()
+            .numeric_name_policy(NumericNamePolicy::PreferId);
+        #[cfg(feature = "groups")]
+        let config = match Resolver::new().user_by_uid(uid)? {
+            Some(_) => config.include_default_supplementary_groups(),
+            None => config,
+        };
+        Ok(config)
+    }
+}
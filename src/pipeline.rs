@@ -0,0 +1,224 @@
+#[cfg(feature = "chroot")]
+use std::ffi::OsStr;
+use std::fmt;
+#[cfg(feature = "chroot")]
+use std::os::unix::ffi::OsStrExt;
+
+use nix::unistd;
+
+#[cfg(feature = "chroot")]
+use super::chroot::*;
+use super::errors::*;
+use super::hardening;
+use super::privdrop::*;
+use super::resolved::*;
+
+/// A step of a `Pipeline`
+///
+/// The steps provided by this crate are `Preload`, `Chroot`, `SetGroups`, `SetGid`,
+/// `SetUid` and `NoNewPrivs`. Applications can implement this trait to add their own.
+pub trait Step: fmt::Debug {
+    /// Perform the step
+    fn run(&self) -> Result<(), PrivDropError>;
+}
+
+/// Load the data that may not be reachable any more after a chroot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Preload;
+
+impl Step for Preload {
+    fn run(&self) -> Result<(), PrivDropError> {
+        PrivDrop::preload()
+    }
+}
+
+#[cfg(feature = "chroot")]
+impl Step for Chroot {
+    fn run(&self) -> Result<(), PrivDropError> {
+        self.enter()
+    }
+}
+
+/// Replace the supplementary groups
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetGroups(pub Vec<libc::gid_t>);
+
+impl Step for SetGroups {
+    fn run(&self) -> Result<(), PrivDropError> {
+        ResolvedPrivDrop::setgroups(&self.0)
+    }
+}
+
+/// Set the real, effective and saved group IDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetGid(pub libc::gid_t);
+
+impl Step for SetGid {
+    fn run(&self) -> Result<(), PrivDropError> {
+        hardening::setresgid(self.0)
+    }
+}
+
+/// Set the real, effective and saved user IDs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SetUid(pub libc::uid_t);
+
+impl Step for SetUid {
+    fn run(&self) -> Result<(), PrivDropError> {
+        hardening::setresuid(self.0)
+    }
+}
+
+/// Prevent the process and its children from gaining privileges through execve()
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NoNewPrivs;
+
+impl Step for NoNewPrivs {
+    fn run(&self) -> Result<(), PrivDropError> {
+        hardening::set_no_new_privs()
+    }
+}
+
+/// An ordered list of steps, run one after the other
+///
+/// This is the low-level engine behind `PrivDrop`, for applications that need to reorder the
+/// steps or to insert their own between them.
+///
+/// # Example
+/// ```no_run
+/// # use privdrop::{PrivDrop, PrivDropError, Step};
+/// # #[derive(Debug)]
+/// # struct OpenLogs;
+/// # impl Step for OpenLogs {
+/// #     fn run(&self) -> Result<(), PrivDropError> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # fn main() -> Result<(), PrivDropError> {
+/// let mut pipeline = PrivDrop::default().chroot("/var/empty").user("nobody").pipeline()?;
+/// pipeline.insert(2, OpenLogs);
+/// pipeline.run()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    steps: Vec<Box<dyn Step>>,
+    skip_verification: bool,
+}
+
+impl Pipeline {
+    /// Create an empty pipeline
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Steps, in the order they are run
+    pub fn steps(&self) -> &[Box<dyn Step>] {
+        &self.steps
+    }
+
+    /// Add a step at the end of the pipeline
+    pub fn push<S: Step + 'static>(&mut self, step: S) -> &mut Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Insert a step at position `index`
+    pub fn insert<S: Step + 'static>(&mut self, index: usize, step: S) -> &mut Self {
+        self.steps.insert(index, Box::new(step));
+        self
+    }
+
+    /// Remove and return the step at position `index`
+    pub fn remove(&mut self, index: usize) -> Box<dyn Step> {
+        self.steps.remove(index)
+    }
+
+    /// Don't check that root privileges can't be regained after the steps have run,
+    /// e.g. if a custom step keeps capabilities on purpose
+    pub fn skip_verification(&mut self) -> &mut Self {
+        self.skip_verification = true;
+        self
+    }
+
+    /// Run the steps in order, stopping at the first error
+    ///
+    /// If the process was running as root and isn't any more, it is then verified that it
+    /// can't switch back to root.
+    pub fn run(&self) -> Result<(), PrivDropError> {
+        let was_root = unistd::geteuid().is_root();
+        for step in &self.steps {
+            step.run()?;
+        }
+        if self.skip_verification || !was_root || unistd::geteuid().is_root() {
+            return Ok(());
+        }
+        if unistd::setuid(unistd::Uid::from_raw(0)).is_ok() {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Root privileges can still be regained",
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl ResolvedPrivDrop {
+    /// Express the changes as a pipeline of steps
+    ///
+    /// Saved IDs are always cleared. This fails if the changes include options that have no
+    /// corresponding step.
+    pub fn pipeline(&self) -> Result<Pipeline, PrivDropError> {
+        let mut rest = self.clone();
+        let mut pipeline = Pipeline::new();
+        #[cfg(feature = "chroot")]
+        if let Some(chroot) = rest.chroot.take() {
+            let mut step = Chroot::new(OsStr::from_bytes(chroot.as_bytes()))?;
+            if std::mem::take(&mut rest.chroot_overlay) {
+                step = step
+                    .overlay()
+                    .mount_propagation(std::mem::take(&mut rest.mount_propagation));
+            }
+            pipeline.push(step);
+        }
+        if let Some(gid) = rest.gid.take() {
+            if !rest.skip_setgroups {
+                pipeline.push(SetGroups(std::mem::take(&mut rest.groups)));
+            }
+            pipeline.push(SetGid(gid));
+        }
+        if let Some(uid) = rest.uid.take() {
+            pipeline.push(SetUid(uid));
+        }
+        if rest.no_new_privs {
+            pipeline.push(NoNewPrivs);
+        }
+        rest.groups.clear();
+        rest.truncated_groups.clear();
+        rest.skip_setgroups = false;
+        rest.clear_saved_ids = false;
+        rest.no_new_privs = false;
+        if rest != ResolvedPrivDrop::default() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Some options are not available as pipeline steps",
+            )));
+        }
+        Ok(pipeline)
+    }
+}
+
+impl PrivDrop {
+    /// Resolve the names, and express the changes as a pipeline of steps, starting with
+    /// `Preload`
+    ///
+    /// See `ResolvedPrivDrop::pipeline()`.
+    pub fn pipeline(&self) -> Result<Pipeline, PrivDropError> {
+        let resolved = self.resolve()?;
+        let mut pipeline = Pipeline::new();
+        pipeline.push(Preload);
+        pipeline.steps.extend(resolved.pipeline()?.steps);
+        Ok(pipeline)
+    }
+}
@@ -0,0 +1,169 @@
+#[cfg(feature = "chroot")]
+use std::ffi::{CString, OsStr};
+#[cfg(feature = "chroot")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(feature = "chroot")]
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "chroot")]
+use super::chroot;
+use super::errors::*;
+#[cfg(all(test, feature = "chroot"))]
+use super::privdrop::*;
+use super::report::*;
+use super::resolved::*;
+
+#[cfg(feature = "chroot")]
+#[test]
+fn test_child_chroots_are_validated() {
+    let template = ForkTemplate::new(PrivDrop::default().resolve().unwrap()).unwrap();
+    assert_eq!(template.clone().child_chroots(["/"]).unwrap().children(), 1);
+    assert!(template.clone().child_chroots(["/", "/tmp"]).is_err());
+    assert!(template.clone().child_chroots(["/nonexistent"]).is_err());
+    let template = ForkTemplate::new(PrivDrop::default().chroot("/").resolve().unwrap()).unwrap();
+    assert_eq!(
+        template
+            .clone()
+            .child_chroots(["usr", "etc"])
+            .unwrap()
+            .children(),
+        2
+    );
+    assert!(template.child_chroots(["usr/.."]).is_err());
+}
+
+#[cfg(feature = "chroot")]
+#[test]
+fn test_fork_unsafe_changes_are_rejected() {
+    let resolved = ResolvedPrivDrop::deserialize("chroot=2f;chroot_overlay=1").unwrap();
+    let e = ForkTemplate::new(resolved).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+    // "/nonexistent"
+    let resolved = ResolvedPrivDrop::deserialize("chroot=2f6e6f6e6578697374656e74").unwrap();
+    assert!(ForkTemplate::new(resolved).is_err());
+}
+
+/// Credentials prepared by a prefork master, to be applied by each child right after `fork()`
+///
+/// Everything, including the per-child variations, is resolved and checked before forking.
+/// `apply()` only performs the system calls: it doesn't allocate nor query the name service.
+/// Changes that can't be applied this way, such as `use_initgroups()` or `chroot_overlay()`,
+/// are rejected when the template is created.
+///
+/// # Example
+/// ```no_run
+/// # use privdrop::{ForkTemplate, PrivDrop};
+/// # fn main() -> Result<(), privdrop::PrivDropError> {
+/// let template = ForkTemplate::new(PrivDrop::default().chroot("/var/jail").user("nobody").resolve()?)?
+///     .child_chroots(["worker0", "worker1"])?;
+/// for child in 0..template.children() {
+///     if unsafe { libc::fork() } == 0 {
+///         template.apply(child).unwrap_or_else(|_| unsafe { libc::_exit(1) });
+///         // ...
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ForkTemplate {
+    resolved: ResolvedPrivDrop,
+    children: Vec<ResolvedPrivDrop>,
+}
+
+impl ForkTemplate {
+    /// Create a template applying the same changes in every child
+    ///
+    /// The changes are checked, and rejected if they can't be applied right after `fork()`.
+    pub fn new(resolved: ResolvedPrivDrop) -> Result<Self, PrivDropError> {
+        resolved.check_fork_safe()?;
+        resolved.check()?;
+        Ok(ForkTemplate {
+            resolved,
+            children: Vec::new(),
+        })
+    }
+
+    /// Chroot every child into its own directory
+    ///
+    /// Relative paths are subdirectories of the template's chroot directory, and can't contain
+    /// `..`. One child is prepared per path, in order. The directories must exist, be owned by
+    /// root, not be writable by other users, and contain the working directory, if any.
+    #[cfg(feature = "chroot")]
+    pub fn child_chroots<I, P>(mut self, paths: I) -> Result<Self, PrivDropError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        if self.resolved.chroot_fd.is_some() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Per-child chroot directories can't be combined with a chroot descriptor",
+            )));
+        }
+        let base = self
+            .resolved
+            .chroot
+            .as_ref()
+            .map(|chroot| PathBuf::from(OsStr::from_bytes(chroot.as_bytes())));
+        let mut children = Vec::new();
+        for path in paths {
+            if path
+                .as_ref()
+                .components()
+                .any(|component| component == Component::ParentDir)
+            {
+                return Err(PrivDropError::from((
+                    ErrorKind::InvalidConfig,
+                    "Per-child chroot directories can't contain '..'",
+                )));
+            }
+            let path = match base {
+                Some(ref base) => base.join(path),
+                None => path.as_ref().to_path_buf(),
+            };
+            let chroot = CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+                PrivDropError::from((ErrorKind::InvalidConfig, "Invalid chroot directory"))
+            })?;
+            chroot::validate(&chroot)?;
+            if let Some(ref chdir) = self.resolved.chdir {
+                chroot::workdir(
+                    Some(&chroot),
+                    None,
+                    Path::new(OsStr::from_bytes(chdir.as_bytes())),
+                )?;
+            }
+            let mut child = self.resolved.clone();
+            child.chroot = Some(chroot);
+            child.check()?;
+            children.push(child);
+        }
+        self.children = children;
+        Ok(self)
+    }
+
+    /// Number of children with their own variation, or `1` if they all share the same changes
+    pub fn children(&self) -> usize {
+        self.children.len().max(1)
+    }
+
+    /// Apply the changes prepared for `child`, a number between `0` and `children() - 1`
+    ///
+    /// Without per-child variations, any number applies the shared changes. They were checked
+    /// before forking, and are not checked again.
+    pub fn apply(&self, child: usize) -> Result<(), PrivDropError> {
+        let resolved = if self.children.is_empty() {
+            &self.resolved
+        } else {
+            self.children.get(child).ok_or_else(|| {
+                PrivDropError::from((
+                    ErrorKind::InvalidConfig,
+                    "No changes were prepared for this child",
+                ))
+            })?
+        };
+        resolved
+            .apply_checked(&mut PartialApplication::default())
+            .map(|_| ())
+    }
+}
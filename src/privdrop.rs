@@ -1,19 +1,244 @@
 use std::ffi::{CString, OsStr, OsString};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::ops::RangeInclusive;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+#[cfg(feature = "chroot")]
+use std::os::unix::io::AsRawFd;
+#[cfg(feature = "chroot")]
+use std::os::unix::io::OwnedFd;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[cfg(all(test, feature = "chroot"))]
 use nix::unistd;
 
+use super::account;
+#[cfg(target_os = "android")]
+use super::android;
+use super::audit;
+#[cfg(all(feature = "caps", target_os = "linux"))]
+use super::capabilities;
+#[cfg(feature = "chroot")]
+use super::chroot;
+use super::diagnostics;
+use super::dirs;
+use super::ephemeral::{self, EphemeralLock};
 use super::errors::*;
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+use super::fdpass;
+#[cfg(feature = "groups")]
+use super::groups::*;
+use super::raw_sockets::*;
+use super::report::*;
+use super::resolved::*;
+use super::resolver::*;
+use super::resources;
+use super::restrictions::*;
+#[cfg(target_os = "linux")]
+use super::watches::*;
 
+#[cfg(feature = "chroot")]
+#[test]
+fn test_forbid_fork_rejects_chroot_escape_check() {
+    let e = PrivDrop::default()
+        .forbid_fork()
+        .check_chroot_escape()
+        .resolve()
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+}
+
+/// Set the option of the setter called `name`, or return `None` if it is not available
+#[cfg(test)]
+fn with_option(config: PrivDrop, name: &str) -> Option<PrivDrop> {
+    Some(match name {
+        "user" => config.user("root"),
+        "group" => config.group("root"),
+        "ephemeral_ids" => config.ephemeral_ids(60000..=61000),
+        "user_from_path_owner" => config.user_from_path_owner("/"),
+        "forbid_fork" => config.forbid_fork(),
+        "chdir_to_runtime_dir" => config.chdir_to_runtime_dir(),
+        #[cfg(feature = "chroot")]
+        "chroot" => config.chroot("/"),
+        #[cfg(feature = "chroot")]
+        "chroot_fd" => config.chroot_fd(OwnedFd::from(std::fs::File::open("/").unwrap())),
+        #[cfg(feature = "chroot")]
+        "chroot_into_users_home" => config.chroot_into_users_home(),
+        #[cfg(feature = "chroot")]
+        "chroot_overlay" => config.chroot_overlay(),
+        #[cfg(feature = "chroot")]
+        "validate_chroot" => config.validate_chroot(),
+        #[cfg(feature = "chroot")]
+        "audit_chroot" => config.audit_chroot(1, 1),
+        #[cfg(feature = "chroot")]
+        "check_chroot_escape" => config.check_chroot_escape(),
+        #[cfg(feature = "groups")]
+        "use_initgroups" => config.use_initgroups(),
+        #[cfg(feature = "groups")]
+        "exclude_groups" => config.exclude_groups(&["root"]),
+        #[cfg(feature = "groups")]
+        "exclude_group_ids" => config.exclude_group_ids(0..=999),
+        #[cfg(feature = "groups")]
+        "restrict_groups_to" => config.restrict_groups_to(&["root"]),
+        #[cfg(feature = "groups")]
+        "group_list" => config.group_list(&["root"]),
+        #[cfg(feature = "groups")]
+        "group_id_list" => config.group_id_list(&[0]),
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        "unprivileged_sandbox" => config.unprivileged_sandbox(0, 0),
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        "keep_caps" => config.keep_caps(&["CAP_NET_ADMIN"]),
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        "keep_net_bind_service" => config.keep_net_bind_service(),
+        // Options that are not available with the enabled features
+        _ => return None,
+    })
+}
+
+#[test]
+fn test_option_conflicts() {
+    for &(a, b, message) in OPTION_CONFLICTS.iter() {
+        let config = with_option(PrivDrop::default(), a).and_then(|x| with_option(x, b));
+        // With all the features, every option of the table must be known.
+        #[cfg(all(
+            feature = "chroot",
+            feature = "groups",
+            feature = "caps",
+            target_os = "linux"
+        ))]
+        assert!(config.is_some(), "Unknown option in {} / {}", a, b);
+        let config = match config {
+            Some(config) => config,
+            None => continue,
+        };
+        assert!(config.has_option(a) && config.has_option(b));
+        let e = config.resolve().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+        // Options implying others, e.g. `keep_net_bind_service()`, can match an earlier pair.
+        assert!(OPTION_CONFLICTS.iter().any(|&(x, y, other)| {
+            e.to_string() == other && config.has_option(x) && config.has_option(y)
+        }));
+        assert!(message.contains(a) && message.contains(b));
+    }
+}
+
+#[test]
+fn test_conflicting_options_are_config_errors() {
+    let configs = [
+        PrivDrop::default()
+            .ephemeral_ids(60000..=61000)
+            .user("root"),
+        PrivDrop::default()
+            .ephemeral_ids(60000..=61000)
+            .group("root"),
+        PrivDrop::default().user_from_path_owner("/").user("root"),
+    ];
+    for config in configs.iter() {
+        assert_eq!(
+            config.resolve().unwrap_err().kind(),
+            ErrorKind::InvalidConfig
+        );
+    }
+    #[cfg(feature = "chroot")]
+    {
+        let e = PrivDrop::default()
+            .chroot("/")
+            .runtime_dir("/run/privdrop-test", 0o700)
+            .chdir_to_runtime_dir()
+            .resolve()
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+    }
+    #[cfg(feature = "groups")]
+    {
+        let e = PrivDrop::default()
+            .include_default_supplementary_groups()
+            .resolve()
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+    }
+}
+
+#[cfg(feature = "groups")]
+#[test]
+fn test_group_filters_reject_unknown_groups() {
+    let unknown = ["privdrop-unknown-group"];
+    assert!(PrivDrop::default()
+        .exclude_groups(&unknown)
+        .resolve()
+        .is_err());
+    assert!(PrivDrop::default()
+        .restrict_groups_to(&unknown)
+        .resolve()
+        .is_err());
+    assert!(PrivDrop::default()
+        .exclude_groups(&["0"])
+        .fallback_to_ids_if_names_are_numeric()
+        .resolve()
+        .is_ok());
+}
+
+#[test]
+fn test_non_utf8_names() {
+    let name = OsStr::from_bytes(b"privdrop-\xff");
+    let mut resolver = Resolver::new();
+    let e = PrivDrop::lookup_user(&mut resolver, name, None).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = PrivDrop::lookup_group(&mut resolver, name, None).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    let e = PrivDrop::lookup_user(&mut resolver, OsStr::from_bytes(b"a\0b"), None).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+}
+
+#[test]
+fn test_ephemeral_ids_not_allocated_on_resolve() {
+    let config = PrivDrop::default()
+        .ephemeral_ids(4_000_000_000..=4_000_000_010)
+        .ephemeral_lock_dir("/nonexistent");
+    let resolved = config.resolve().unwrap();
+    assert_eq!((resolved.uid, resolved.gid), (None, None));
+    assert!(resolved.groups.is_empty());
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_sandbox_rejects_users() {
+    let e = PrivDrop::default()
+        .user("0")
+        .fallback_to_ids_if_names_are_numeric()
+        .unprivileged_sandbox(0, 0)
+        .apply()
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+}
+
+#[cfg(feature = "chroot")]
 #[test]
 fn test_privdrop() {
     if unistd::geteuid().is_root() {
-        PrivDrop::default()
-            .chroot("/var/empty")
-            .user("nobody")
-            .apply()
-            .unwrap_or_else(|e| panic!("Failed to drop privileges: {}", e));
+        // The changes are applied in a child process, so that the other tests are not confined.
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            let status = match PrivDrop::default()
+                .chroot("/var/empty")
+                .user("nobody")
+                .apply()
+            {
+                Ok(()) => 0,
+                Err(e) => {
+                    eprintln!("Failed to drop privileges: {}", e);
+                    1
+                }
+            };
+            unsafe { libc::_exit(status) };
+        }
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
     } else {
         eprintln!("Test was skipped because it needs to be run as root.");
     }
@@ -29,30 +254,437 @@ fn test_privdrop() {
 ///     .apply()
 ///     .unwrap_or_else(|e| { panic!("Failed to drop privileges: {}", e) });
 /// ```
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
 pub struct PrivDrop {
-    chroot: Option<PathBuf>,
-    user: Option<OsString>,
-    group: Option<OsString>,
-    group_list: Option<Vec<OsString>>,
-    include_default_supplementary_groups: bool,
-    fallback_to_ids_if_names_are_numeric: bool,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot: Option<PathBuf>,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_fd: Option<chroot::ChrootFd>,
+    pub(crate) user: Option<OsString>,
+    pub(crate) group: Option<OsString>,
+    #[cfg(feature = "groups")]
+    pub(crate) group_list: Option<Vec<OsString>>,
+    #[cfg(feature = "groups")]
+    pub(crate) group_id_list: Option<Vec<libc::gid_t>>,
+    #[cfg(feature = "groups")]
+    pub(crate) include_default_supplementary_groups: bool,
+    #[cfg(feature = "groups")]
+    pub(crate) use_initgroups: bool,
+    #[cfg(feature = "groups")]
+    pub(crate) supplementary_groups_from_user: Option<OsString>,
+    #[cfg(feature = "groups")]
+    pub(crate) excluded_groups: Vec<OsString>,
+    #[cfg(feature = "groups")]
+    pub(crate) excluded_group_ids: Vec<RangeInclusive<libc::gid_t>>,
+    #[cfg(feature = "groups")]
+    pub(crate) allowed_groups: Option<Vec<OsString>>,
+    #[cfg(feature = "groups")]
+    pub(crate) group_limit_policy: GroupLimitPolicy,
+    pub(crate) fallback_to_ids_if_names_are_numeric: bool,
+    pub(crate) numeric_name_policy: NumericNamePolicy,
+    pub(crate) reject_expired_account: bool,
+    pub(crate) min_target_uid: Option<libc::uid_t>,
+    pub(crate) allowed_target_uids: Vec<libc::uid_t>,
+    pub(crate) audit_home_dir: bool,
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    pub(crate) keep_caps: Vec<String>,
+    pub(crate) check_executable_not_writable: bool,
+    pub(crate) sensitive_paths: Option<Vec<PathBuf>>,
+    pub(crate) adapt_to_restrictions: bool,
+    pub(crate) deny_setuid_family: bool,
+    pub(crate) clear_saved_ids: bool,
+    pub(crate) no_new_privs: bool,
+    pub(crate) disable_core_dumps: bool,
+    pub(crate) umask: Option<libc::mode_t>,
+    pub(crate) clear_env: bool,
+    pub(crate) cloexec_fds: bool,
+    pub(crate) lock_securebits: bool,
+    pub(crate) disabled_speculation: Vec<Speculation>,
+    pub(crate) disable_tracing: bool,
+    pub(crate) ptracer: Option<Ptracer>,
+    pub(crate) max_processes: Option<libc::rlim_t>,
+    pub(crate) forbid_fork: bool,
+    pub(crate) ephemeral_ids: Option<RangeInclusive<libc::uid_t>>,
+    pub(crate) ephemeral_lock_dir: Option<PathBuf>,
+    pub(crate) path_owner: Option<PathBuf>,
+    pub(crate) runtime_dir: Option<(PathBuf, libc::mode_t)>,
+    pub(crate) chdir_to_runtime_dir: bool,
+    pub(crate) state_dir: Option<(PathBuf, libc::mode_t)>,
+    pub(crate) cache_dir: Option<(PathBuf, libc::mode_t)>,
+    pub(crate) files: Vec<(PathBuf, bool)>,
+    pub(crate) tls_key_pairs: Vec<(PathBuf, PathBuf)>,
+    pub(crate) raw_sockets: Vec<RawSocket>,
+    #[cfg(target_os = "linux")]
+    pub(crate) watches: Vec<Watch>,
+    pub(crate) controlling_tty: ControllingTty,
+    pub(crate) explain_failures: bool,
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+    pub(crate) sched_policy: Option<(SchedPolicy, libc::c_int)>,
+    pub(crate) routing_table: Option<libc::c_int>,
+    pub(crate) hostname: Option<OsString>,
+    pub(crate) notify_fd: Option<RawFd>,
+    #[cfg(feature = "chroot")]
+    pub(crate) validate_chroot: bool,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_audit_limits: Option<(usize, usize)>,
+    #[cfg(feature = "chroot")]
+    pub(crate) check_chroot_escape: bool,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_overlay: bool,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_into_users_home: bool,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_workdir: Option<PathBuf>,
+    #[cfg(feature = "chroot")]
+    pub(crate) order: StepOrder,
+    pub(crate) mount_propagation: MountPropagation,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) unprivileged_sandbox: Option<(libc::uid_t, libc::gid_t)>,
+    pub(crate) on_failure: Option<FailureHook>,
+}
+
+type FailureFn = dyn Fn(&PrivDropError, &PartialApplication) + Send + Sync;
+
+/// Function called by `PrivDrop::on_failure()`, only equal to itself
+#[derive(Clone)]
+pub(crate) struct FailureHook(Arc<FailureFn>);
+
+impl fmt::Debug for FailureHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FailureHook")
+    }
+}
+
+impl PartialEq for FailureHook {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for FailureHook {}
+
+/// What to do with the controlling terminal when applying the changes
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControllingTty {
+    /// Keep the controlling terminal, e.g. for interactive debugging
+    #[default]
+    Keep,
+    /// Detach from the controlling terminal before switching users, so that the unprivileged
+    /// process can't inject input into it
+    Detach,
 }
 
+/// What to do when there are more supplementary groups than the system allows
+#[cfg(feature = "groups")]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupLimitPolicy {
+    /// Fail to apply the changes
+    #[default]
+    Error,
+    /// Keep the primary group, then the groups in the order they were computed, and record
+    /// the other ones in `AppliedPrivileges::truncated_groups`
+    Truncate,
+    /// Like `Truncate`, but keep the groups set with `group_list()` and `group_id_list()`
+    /// before the default supplementary groups
+    PreferExplicit,
+}
+
+/// What to do when a name that is a valid number is also the name of an account with a
+/// different ID, if `fallback_to_ids_if_names_are_numeric()` was set
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericNamePolicy {
+    /// Use the account with that name
+    #[default]
+    PreferName,
+    /// Use the number as an ID, ignoring the account with that name
+    PreferId,
+    /// Fail to resolve the name
+    Error,
+}
+
+/// Order in which the root directory and the groups are changed
+#[cfg(feature = "chroot")]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepOrder {
+    /// Change the root directory, then the groups
+    #[default]
+    ChrootFirst,
+    /// Change the groups, then the root directory, e.g. if the chroot directory can only be
+    /// traversed by the target group
+    GroupsFirst,
+}
+
+/// How mounts performed in a new mount namespace relate to the parent namespace
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum MountPropagation {
+    /// Mounts are not propagated in either direction
+    #[default]
+    Private,
+    /// Mounts from the parent namespace are still propagated to the new namespace, but not
+    /// the other way round
+    Slave,
+}
+
+/// Speculative execution feature that can be disabled with `PrivDrop::disable_speculation()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Speculation {
+    /// Speculative store bypass (Spectre variant 4)
+    StoreBypass,
+    /// Indirect branch speculation (Spectre variant 2)
+    IndirectBranch,
+}
+
+/// Processes allowed to trace the process once it runs as a non-root user, when Yama
+/// restricts ptrace to the ancestors of a process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Ptracer {
+    /// Only the processes allowed by the Yama policy, usually the ancestors of the process
+    Restricted,
+    /// The process with the given ID, e.g. a supervisor that is not an ancestor, in addition
+    /// to the ones allowed by the Yama policy
+    Process(libc::pid_t),
+    /// Any process running as the same user
+    Any,
+}
+
+/// Realtime scheduling policy
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum SchedPolicy {
+    /// First in, first out (`SCHED_FIFO`)
+    Fifo,
+    /// Round robin (`SCHED_RR`)
+    RoundRobin,
+}
+
+/// Number of groups from which group names are looked up concurrently
+#[cfg(feature = "groups")]
+const PARALLEL_LOOKUPS_MIN_GROUPS: usize = 8;
+
+/// Maximum number of concurrent group lookups
+#[cfg(feature = "groups")]
+const MAX_PARALLEL_LOOKUPS: usize = 8;
+
+/// Options that can't be combined, named after their setters, and the error describing the
+/// conflict
+///
+/// Resolving a configuration fails if both options of a pair are set, and `privdrop!` rejects
+/// them at compile time.
+pub(crate) const OPTION_CONFLICTS: [(&str, &str, &str); 25] = [
+    (
+        "ephemeral_ids",
+        "user",
+        "ephemeral_ids() can't be combined with user()",
+    ),
+    (
+        "ephemeral_ids",
+        "group",
+        "ephemeral_ids() can't be combined with group()",
+    ),
+    (
+        "user_from_path_owner",
+        "user",
+        "user_from_path_owner() can't be combined with user()",
+    ),
+    (
+        "user_from_path_owner",
+        "ephemeral_ids",
+        "user_from_path_owner() can't be combined with ephemeral_ids()",
+    ),
+    (
+        "chroot",
+        "chroot_fd",
+        "chroot() can't be combined with chroot_fd()",
+    ),
+    (
+        "chroot_into_users_home",
+        "chroot",
+        "chroot_into_users_home() can't be combined with chroot()",
+    ),
+    (
+        "chroot_into_users_home",
+        "chroot_fd",
+        "chroot_into_users_home() can't be combined with chroot_fd()",
+    ),
+    (
+        "chroot_overlay",
+        "chroot_fd",
+        "chroot_overlay() can't be combined with chroot_fd()",
+    ),
+    (
+        "validate_chroot",
+        "chroot_fd",
+        "validate_chroot() can't be combined with chroot_fd()",
+    ),
+    (
+        "audit_chroot",
+        "chroot_fd",
+        "audit_chroot() can't be combined with chroot_fd()",
+    ),
+    (
+        "chdir_to_runtime_dir",
+        "chroot",
+        "chdir_to_runtime_dir() can't be combined with chroot()",
+    ),
+    (
+        "chdir_to_runtime_dir",
+        "chroot_fd",
+        "chdir_to_runtime_dir() can't be combined with chroot_fd()",
+    ),
+    (
+        "chdir_to_runtime_dir",
+        "chroot_into_users_home",
+        "chdir_to_runtime_dir() can't be combined with chroot_into_users_home()",
+    ),
+    (
+        "forbid_fork",
+        "check_chroot_escape",
+        "forbid_fork() can't be combined with check_chroot_escape()",
+    ),
+    (
+        "use_initgroups",
+        "exclude_groups",
+        "use_initgroups() can't be combined with exclude_groups()",
+    ),
+    (
+        "use_initgroups",
+        "exclude_group_ids",
+        "use_initgroups() can't be combined with exclude_group_ids()",
+    ),
+    (
+        "use_initgroups",
+        "restrict_groups_to",
+        "use_initgroups() can't be combined with restrict_groups_to()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "user",
+        "unprivileged_sandbox() can't be combined with user()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "group",
+        "unprivileged_sandbox() can't be combined with group()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "ephemeral_ids",
+        "unprivileged_sandbox() can't be combined with ephemeral_ids()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "user_from_path_owner",
+        "unprivileged_sandbox() can't be combined with user_from_path_owner()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "group_list",
+        "unprivileged_sandbox() can't be combined with group_list()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "group_id_list",
+        "unprivileged_sandbox() can't be combined with group_id_list()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "keep_caps",
+        "unprivileged_sandbox() can't be combined with keep_caps()",
+    ),
+    (
+        "unprivileged_sandbox",
+        "keep_net_bind_service",
+        "unprivileged_sandbox() can't be combined with keep_net_bind_service()",
+    ),
+];
+
 #[derive(Default, Clone, Debug)]
 struct UserIds {
     uid: Option<libc::uid_t>,
     gid: Option<libc::gid_t>,
+    #[cfg(feature = "groups")]
     group_list: Option<Vec<libc::gid_t>>,
+    /// Account name, if the user was given as a numeric ID
+    #[cfg(feature = "groups")]
+    name: Option<OsString>,
 }
 
 impl PrivDrop {
+    /// Preset confining a service to a chroot directory as a non-root user, with strict defaults
+    ///
+    /// The chroot directory is validated, supplementary groups are cleared, the saved user and
+    /// group IDs are reset, core dumps are disabled, and, on Linux, `no_new_privs` is set.
+    /// The returned configuration can be further customized before being applied.
+    #[cfg(feature = "chroot")]
+    pub fn jail<T: AsRef<Path>, S: AsRef<OsStr>>(path: T, user: S) -> Self {
+        let jail = PrivDrop::default()
+            .chroot(path)
+            .validate_chroot()
+            .user(user)
+            .clear_saved_ids()
+            .disable_core_dumps();
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let jail = jail.no_new_privs();
+        jail
+    }
+
     /// chroot() to a specific directory before switching to a non-root user
+    #[cfg(feature = "chroot")]
     pub fn chroot<T: AsRef<Path>>(mut self, path: T) -> Self {
         self.chroot = Some(path.as_ref().to_owned());
         self
     }
 
+    /// chroot() to the home directory of the target user, as found in the user database
+    ///
+    /// This is the usual setup for SFTP-like and per-user services. The directory goes
+    /// through the same checks as any other chroot directory, and can't be combined with
+    /// another one.
+    #[cfg(feature = "chroot")]
+    pub fn chroot_into_users_home(mut self) -> Self {
+        self.chroot_into_users_home = true;
+        self
+    }
+
+    /// Set whether the groups are changed before or after the root directory
+    /// (default: `StepOrder::ChrootFirst`)
+    #[cfg(feature = "chroot")]
+    pub fn order(mut self, order: StepOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// After the chroot() call, change the working directory to `path`, relative to the new
+    /// root directory, instead of `/`
+    ///
+    /// The directory must exist when the configuration is resolved.
+    #[cfg(feature = "chroot")]
+    pub fn chroot_workdir<T: AsRef<Path>>(mut self, path: T) -> Self {
+        self.chroot_workdir = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// chroot() to an open directory, e.g. a jail prepared by a privileged launcher
+    ///
+    /// No path is involved: the process changes to the directory with `fchdir()`, then calls
+    /// `chroot(".")`. The descriptor is shared by the clones of the configuration and by the
+    /// credentials resolved from it, and is closed when the last of them is dropped.
+    #[cfg(feature = "chroot")]
+    pub fn chroot_fd(mut self, fd: OwnedFd) -> Self {
+        self.chroot_fd = Some(chroot::ChrootFd::new(fd));
+        self
+    }
+
+    /// Like `chroot_fd()`, with a directory descriptor received over a Unix socket
+    /// (`SCM_RIGHTS`)
+    #[cfg(all(feature = "chroot", target_os = "linux"))]
+    pub fn recv_chroot_fd<S: AsRawFd>(self, socket: &S) -> Result<Self, PrivDropError> {
+        Ok(self.chroot_fd(fdpass::recv_fd(socket.as_raw_fd())?))
+    }
+
     /// Set the name of a user to switch to
     pub fn user<S: AsRef<OsStr>>(mut self, user: S) -> Self {
         self.user = Some(user.as_ref().to_owned());
@@ -66,255 +698,1520 @@ impl PrivDrop {
     }
 
     /// Include default supplementary groups
+    #[cfg(feature = "groups")]
     pub fn include_default_supplementary_groups(mut self) -> Self {
         self.include_default_supplementary_groups = true;
         self
     }
 
+    /// Use initgroups() to set the default supplementary groups of the user
+    #[cfg(feature = "groups")]
+    pub fn use_initgroups(mut self) -> Self {
+        self.include_default_supplementary_groups = true;
+        self.use_initgroups = true;
+        self
+    }
+
+    /// Include the default supplementary groups of another user
+    ///
+    /// This can be used to run as a user, with the group memberships of a different account.
+    #[cfg(feature = "groups")]
+    pub fn supplementary_groups_from_user<S: AsRef<OsStr>>(mut self, user: S) -> Self {
+        self.supplementary_groups_from_user = Some(user.as_ref().to_owned());
+        self
+    }
+
+    /// Remove the given groups (e.g. "sudo", "adm") from the default supplementary groups
+    ///
+    /// Groups set with `group_list()` and the primary group are kept. Resolving fails if one
+    /// of the groups can't be found. This can't be combined with `use_initgroups()`.
+    #[cfg(feature = "groups")]
+    pub fn exclude_groups<S: AsRef<OsStr>>(mut self, groups: &[S]) -> Self {
+        self.excluded_groups
+            .extend(groups.iter().map(|x| x.as_ref().to_owned()));
+        self
+    }
+
+    /// Remove the supplementary groups whose ID is within `range`, e.g. `0..=999` for system
+    /// groups
+    ///
+    /// This applies to all the supplementary groups, including the ones set with
+    /// `group_list()`, but the primary group is kept. It can be called multiple times to
+    /// exclude several ranges, and can't be combined with `use_initgroups()`.
+    #[cfg(feature = "groups")]
+    pub fn exclude_group_ids(mut self, range: RangeInclusive<libc::gid_t>) -> Self {
+        self.excluded_group_ids.push(range);
+        self
+    }
+
+    /// Only keep the supplementary groups that are in the given list, whatever the group
+    /// database and the other options say
+    ///
+    /// Applying the changes fails if the primary group is not in the list, or if one of the
+    /// groups can't be found. This can't be combined with `use_initgroups()`.
+    #[cfg(feature = "groups")]
+    pub fn restrict_groups_to<S: AsRef<OsStr>>(mut self, groups: &[S]) -> Self {
+        self.allowed_groups = Some(groups.iter().map(|x| x.as_ref().to_owned()).collect());
+        self
+    }
+
     /// If a name is not found, try to parse it as a numeric identifier
+    ///
+    /// If an account exists with a numeric user ID, its primary group and default supplementary
+    /// groups are used as if it had been given by name.
     pub fn fallback_to_ids_if_names_are_numeric(mut self) -> Self {
         self.fallback_to_ids_if_names_are_numeric = true;
         self
     }
 
+    /// Set how names that are both valid numbers and account names are interpreted
+    ///
+    /// This only matters if `fallback_to_ids_if_names_are_numeric()` was set. By default, the
+    /// account with that name is used.
+    pub fn numeric_name_policy(mut self, policy: NumericNamePolicy) -> Self {
+        self.numeric_name_policy = policy;
+        self
+    }
+
+    /// Refuse to switch to a user whose account has expired, e.g. a decommissioned account
+    ///
+    /// The expiration date is read from the shadow database on Linux, and from the password
+    /// database on BSD systems and macOS. Locked passwords are not taken into account, as
+    /// service accounts usually don't have a usable password.
+    pub fn reject_expired_account(mut self) -> Self {
+        self.reject_expired_account = true;
+        self
+    }
+
+    /// Refuse to switch to a user ID lower than `min_uid`, e.g. to prevent dropping to system
+    /// accounts such as `daemon` or `bin` by mistake
+    pub fn min_target_uid(mut self, min_uid: libc::uid_t) -> Self {
+        self.min_target_uid = Some(min_uid);
+        self
+    }
+
+    /// Allow switching to the given user ID even if it is lower than `min_target_uid()`
+    pub fn allow_target_uid(mut self, uid: libc::uid_t) -> Self {
+        self.allowed_target_uids.push(uid);
+        self
+    }
+
+    /// Before applying the changes, check that the home directory of the target user exists,
+    /// is owned by that user, and is not writable by its group or by other users
+    ///
+    /// The path is checked before entering the chroot, if any.
+    pub fn audit_home_dir(mut self) -> Self {
+        self.audit_home_dir = true;
+        self
+    }
+
+    /// Retain the given capabilities (e.g. "CAP_NET_ADMIN") after switching to a non-root user
+    ///
+    /// The capabilities are kept in the permitted, effective, inheritable and ambient sets.
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    pub fn keep_caps<S: ToString>(mut self, caps: &[S]) -> Self {
+        self.keep_caps.extend(caps.iter().map(|x| x.to_string()));
+        self
+    }
+
+    /// Keep the ability to bind to privileged ports after switching to a non-root user
+    ///
+    /// This is a shortcut for `keep_caps(&["CAP_NET_BIND_SERVICE"])`.
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    pub fn keep_net_bind_service(self) -> Self {
+        self.keep_caps(&["CAP_NET_BIND_SERVICE"])
+    }
+
+    /// After the changes have been applied, fail if the new identity can modify the running executable
+    ///
+    /// Both the executable and the directory containing it are checked.
+    pub fn check_executable_not_writable(mut self) -> Self {
+        self.check_executable_not_writable = true;
+        self
+    }
+
+    /// Before applying the changes, fail if a writable file descriptor refers to a sensitive location
+    ///
+    /// `/etc` and the directory of the running executable are always considered sensitive.
+    /// Additional locations can be given in `sensitive_paths`.
+    pub fn check_writable_fds<P: AsRef<Path>>(mut self, sensitive_paths: &[P]) -> Self {
+        self.sensitive_paths
+            .get_or_insert_with(Vec::new)
+            .extend(sensitive_paths.iter().map(|x| x.as_ref().to_owned()));
+        self
+    }
+
+    /// Skip the operations that the environment doesn't permit, instead of failing
+    ///
+    /// For example, the chroot() call is skipped in a user namespace lacking `CAP_SYS_CHROOT`,
+    /// and supplementary groups are left untouched if `setgroups()` has been denied.
+    pub fn adapt_to_restrictions(mut self) -> Self {
+        self.adapt_to_restrictions = true;
+        self
+    }
+
+    /// Confine a process that is not running as root, using a new user namespace
+    ///
+    /// The current uid and gid are mapped to `ns_uid` and `ns_gid` within the namespace,
+    /// the chroot directory, if any, is entered in a new mount namespace, and all capabilities
+    /// are then dropped. Users and groups can't be changed in this mode, and, as `setgroups()`
+    /// has to be denied in the namespace, neither can supplementary groups. The other options
+    /// and checks are applied as usual. The process must be single-threaded.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn unprivileged_sandbox(mut self, ns_uid: libc::uid_t, ns_gid: libc::gid_t) -> Self {
+        self.unprivileged_sandbox = Some((ns_uid, ns_gid));
+        self
+    }
+
+    /// After the changes have been applied, install a seccomp filter making any further
+    /// credential change (setuid(), setgid(), setgroups()...) fail with `EPERM`
+    ///
+    /// This is only supported on Linux, on x86_64 and aarch64.
+    pub fn deny_setuid_family(mut self) -> Self {
+        self.deny_setuid_family = true;
+        self
+    }
+
+    /// Before applying the changes, check that the chroot directory is owned by root and is
+    /// not writable by other users
+    ///
+    /// This requires a chroot path, not a descriptor.
+    #[cfg(feature = "chroot")]
+    pub fn validate_chroot(mut self) -> Self {
+        self.validate_chroot = true;
+        self
+    }
+
+    /// Before applying the changes, scan the chroot directory and fail if it contains setuid
+    /// or setgid files, or device nodes
+    ///
+    /// The scan also fails if the tree is deeper than `max_depth` levels or contains more
+    /// than `max_entries` entries. This requires a chroot path, not a descriptor.
+    #[cfg(feature = "chroot")]
+    pub fn audit_chroot(mut self, max_depth: usize, max_entries: usize) -> Self {
+        self.chroot_audit_limits = Some((max_depth, max_entries));
+        self
+    }
+
+    /// Use the chroot directory as a read-only template: move the process to a new mount
+    /// namespace, and mount an overlay of the directory over an empty tmpfs in its place
+    /// before entering it (Linux only)
+    ///
+    /// The service can write to its root directory, and the template on disk is never modified.
+    /// Changes are only lost once every process in the new mount namespace, including children
+    /// of the service, has exited. Whatever `mount_propagation()` is set to, the overlay is never
+    /// propagated to the parent namespace. This requires a chroot path, not a descriptor.
+    #[cfg(feature = "chroot")]
+    pub fn chroot_overlay(mut self) -> Self {
+        self.chroot_overlay = true;
+        self
+    }
+
+    /// Call `hook` if applying the changes fails after some of them have already been made
+    ///
+    /// The hook receives the error and the list of changes that were made. These can't be
+    /// undone, so this is mostly useful to report the state of the process to a supervisor
+    /// before exiting. It is not called if the process was left unchanged.
+    pub fn on_failure<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&PrivDropError, &PartialApplication) + Send + Sync + 'static,
+    {
+        self.on_failure = Some(FailureHook(Arc::new(hook)));
+        self
+    }
+
+    /// Set the propagation of the existing mounts when a mount namespace is created, before
+    /// anything is mounted in it (default: `MountPropagation::Private`)
+    ///
+    /// Either way, mounts performed by this crate never propagate back to the parent namespace.
+    pub fn mount_propagation(mut self, propagation: MountPropagation) -> Self {
+        self.mount_propagation = propagation;
+        self
+    }
+
+    /// After the changes have been applied, fork a child that tries the classic techniques to
+    /// escape the chroot, and fail if any of them succeeds
+    ///
+    /// This can't be combined with `forbid_fork()`.
+    #[cfg(feature = "chroot")]
+    pub fn check_chroot_escape(mut self) -> Self {
+        self.check_chroot_escape = true;
+        self
+    }
+
+    /// Also reset the saved user and group IDs, so that they can't be restored later
+    pub fn clear_saved_ids(mut self) -> Self {
+        self.clear_saved_ids = true;
+        self
+    }
+
+    /// After the changes have been applied, prevent the process from gaining new privileges
+    /// by executing setuid programs (Linux only)
+    pub fn no_new_privs(mut self) -> Self {
+        self.no_new_privs = true;
+        self
+    }
+
+    /// After the changes have been applied, prevent the process from writing core dumps
+    pub fn disable_core_dumps(mut self) -> Self {
+        self.disable_core_dumps = true;
+        self
+    }
+
+    /// Enable all the hardening options that don't depend on the filesystem layout
+    ///
+    /// This sets the umask to `077`, clears the environment, sets the close-on-exec flag on
+    /// all file descriptors but the standard ones, disables core dumps, locks the securebits
+    /// on Linux, and disables tracing on FreeBSD. Setters called afterwards take precedence.
+    pub fn hardened(self) -> Self {
+        let hardened = self
+            .umask(0o077)
+            .clear_env(true)
+            .cloexec_fds(true)
+            .disable_core_dumps();
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let hardened = hardened.lock_securebits(true);
+        #[cfg(target_os = "freebsd")]
+        let hardened = hardened.disable_tracing();
+        hardened
+    }
+
+    /// After switching to a non-root user, prevent the process from creating new processes,
+    /// so that a compromised process can't spawn helpers
+    ///
+    /// The process limit is set to the number of processes and threads already running as the
+    /// target user, counted right before the changes are applied, this process included.
+    /// Threads count as processes, so new threads can't be created either. This can't be
+    /// combined with `check_chroot_escape()`, which forks.
+    pub fn forbid_fork(mut self) -> Self {
+        self.forbid_fork = true;
+        self
+    }
+
+    /// After switching to a non-root user, limit the number of processes with `RLIMIT_NPROC`
+    ///
+    /// The limit applies to all the processes and threads running as the target user, not
+    /// only to the children of this process, and is checked when a new one is created.
+    /// It has to be set after the user change, as the limit is enforced per user.
+    pub fn max_processes(mut self, limit: libc::rlim_t) -> Self {
+        self.max_processes = Some(limit);
+        self
+    }
+
+    /// After the changes have been applied, set the file mode creation mask
+    pub fn umask(mut self, umask: libc::mode_t) -> Self {
+        self.umask = Some(umask);
+        self
+    }
+
+    /// After the changes have been applied, remove all the environment variables
+    pub fn clear_env(mut self, clear_env: bool) -> Self {
+        self.clear_env = clear_env;
+        self
+    }
+
+    /// After the changes have been applied, set the close-on-exec flag on all file descriptors
+    /// except the standard input, output and error
+    pub fn cloexec_fds(mut self, cloexec_fds: bool) -> Self {
+        self.cloexec_fds = cloexec_fds;
+        self
+    }
+
+    /// Before switching to a non-root user, lock the securebits so that the process can't
+    /// regain capabilities by executing setuid-root programs (Linux only)
+    pub fn lock_securebits(mut self, lock_securebits: bool) -> Self {
+        self.lock_securebits = lock_securebits;
+        self
+    }
+
+    /// Before switching to a non-root user, disable a speculative execution feature for the
+    /// process and its children with `PR_SET_SPECULATION_CTRL` (Linux only)
+    ///
+    /// The mitigation is force-enabled, so that it can't be turned off afterwards. This is a
+    /// no-op if the CPU is not affected. Can be called multiple times to disable several
+    /// features.
+    pub fn disable_speculation(mut self, speculation: Speculation) -> Self {
+        if !self.disabled_speculation.contains(&speculation) {
+            self.disabled_speculation.push(speculation);
+        }
+        self
+    }
+
+    /// Instead of a named user, switch to an unused user and group ID picked from `range`
+    ///
+    /// An ID is considered unused if neither a user nor a group is assigned to it. The ID is
+    /// picked when the changes are applied. The same value is used as the user ID and as the
+    /// group ID, and is returned in the report.
+    pub fn ephemeral_ids(mut self, range: RangeInclusive<libc::uid_t>) -> Self {
+        self.ephemeral_ids = Some(range);
+        self
+    }
+
+    /// Lock ephemeral IDs in a directory, so that processes sharing it never use the same ID
+    ///
+    /// The lock is returned in `AppliedPrivileges::ephemeral_lock`, and released once the report
+    /// is dropped. With `apply()`, it is held until the process exits.
+    pub fn ephemeral_lock_dir<T: AsRef<Path>>(mut self, lock_dir: T) -> Self {
+        self.ephemeral_lock_dir = Some(lock_dir.as_ref().to_owned());
+        self
+    }
+
+    /// Instead of a named user, switch to the user and group owning the given path
+    ///
+    /// The path is examined when the configuration is resolved. Symbolic links are followed.
+    pub fn user_from_path_owner<T: AsRef<Path>>(mut self, path: T) -> Self {
+        self.path_owner = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Before applying the changes, create a runtime directory owned by the target user and group
+    ///
+    /// If the directory already exists, its owner and mode are updated.
+    pub fn runtime_dir<T: AsRef<Path>>(mut self, path: T, mode: libc::mode_t) -> Self {
+        self.runtime_dir = Some((path.as_ref().to_owned(), mode));
+        self
+    }
+
+    /// Change the working directory to the runtime directory
+    ///
+    /// This can't be combined with `chroot()`.
+    pub fn chdir_to_runtime_dir(mut self) -> Self {
+        self.chdir_to_runtime_dir = true;
+        self
+    }
+
+    /// Before applying the changes, create a directory for persistent state, owned by the
+    /// target user and group
+    ///
+    /// If the directory already exists, its content is left untouched, but its owner and
+    /// mode are updated.
+    pub fn state_dir<T: AsRef<Path>>(mut self, path: T, mode: libc::mode_t) -> Self {
+        self.state_dir = Some((path.as_ref().to_owned(), mode));
+        self
+    }
+
+    /// Before applying the changes, create a cache directory owned by the target user and group
+    ///
+    /// If the directory already exists, its content is left untouched, but its owner and
+    /// mode are updated.
+    pub fn cache_dir<T: AsRef<Path>>(mut self, path: T, mode: libc::mode_t) -> Self {
+        self.cache_dir = Some((path.as_ref().to_owned(), mode));
+        self
+    }
+
+    /// Open a file for reading before dropping privileges
+    ///
+    /// The file is returned by `apply_with_files()`.
+    pub fn open_before_drop<T: AsRef<Path>>(mut self, path: T) -> Self {
+        self.files.push((path.as_ref().to_owned(), false));
+        self
+    }
+
+    /// Open a file for appending before dropping privileges, creating it if it doesn't exist
+    ///
+    /// The file is returned by `apply_with_files()`.
+    pub fn append_before_drop<T: AsRef<Path>>(mut self, path: T) -> Self {
+        self.files.push((path.as_ref().to_owned(), true));
+        self
+    }
+
+    /// Read a certificate chain and its private key before dropping privileges
+    ///
+    /// The content of the files is returned by `apply_with_tls_key_pairs()`, so that the
+    /// private key file can remain readable by root only.
+    pub fn tls_key_pair<C: AsRef<Path>, K: AsRef<Path>>(
+        mut self,
+        cert_chain: C,
+        private_key: K,
+    ) -> Self {
+        self.tls_key_pairs.push((
+            cert_chain.as_ref().to_owned(),
+            private_key.as_ref().to_owned(),
+        ));
+        self
+    }
+
+    /// Create a raw socket before dropping privileges, e.g. to send ICMP echo requests
+    ///
+    /// The socket is returned by `apply_with_raw_sockets()`. Creating raw sockets requires root
+    /// privileges or the `CAP_NET_RAW` capability, that the process doesn't need to retain.
+    pub fn raw_socket(mut self, socket: RawSocket) -> Self {
+        self.raw_sockets.push(socket);
+        self
+    }
+
+    /// Watch a path with inotify, with an event mask such as `IN_MODIFY`, before dropping
+    /// privileges (Linux only)
+    ///
+    /// The watches share a single inotify instance, returned by `apply_with_watches()`.
+    #[cfg(target_os = "linux")]
+    pub fn inotify_watch<T: AsRef<Path>>(mut self, path: T, mask: u32) -> Self {
+        self.watches
+            .push(Watch::Inotify(path.as_ref().to_owned(), mask));
+        self
+    }
+
+    /// Add a fanotify mark on a path, with flags such as `FAN_MARK_MOUNT` and an event mask
+    /// such as `FAN_OPEN_PERM`, before dropping privileges (Linux only)
+    ///
+    /// The marks share a single fanotify group, returned by `apply_with_watches()`. fanotify
+    /// requires the `CAP_SYS_ADMIN` capability, that the process doesn't need to retain.
+    #[cfg(target_os = "linux")]
+    pub fn fanotify_mark<T: AsRef<Path>>(
+        mut self,
+        path: T,
+        flags: libc::c_uint,
+        mask: u64,
+    ) -> Self {
+        self.watches
+            .push(Watch::Fanotify(path.as_ref().to_owned(), flags, mask));
+        self
+    }
+
+    /// Keep or detach from the controlling terminal
+    ///
+    /// The terminal is kept by default.
+    pub fn controlling_tty(mut self, controlling_tty: ControllingTty) -> Self {
+        self.controlling_tty = controlling_tty;
+        self
+    }
+
+    /// Before switching to a non-root user, restrict the process to the given CPUs (Linux only)
+    ///
+    /// Depending on the system configuration, an unprivileged process may not be able to
+    /// add CPUs to its affinity mask later.
+    pub fn cpu_affinity(mut self, cpus: &[usize]) -> Self {
+        self.cpu_affinity = Some(cpus.to_vec());
+        self
+    }
+
+    /// Before switching to a non-root user, set a realtime scheduling policy (Linux only)
+    ///
+    /// Realtime policies require privileges, so they have to be set before the drop.
+    pub fn sched_policy(mut self, policy: SchedPolicy, priority: libc::c_int) -> Self {
+        self.sched_policy = Some((policy, priority));
+        self
+    }
+
+    /// Before switching to a non-root user, prevent the process from being traced or dumped
+    /// using `procctl(PROC_TRACE_CTL)` (FreeBSD only)
+    ///
+    /// On Linux, `disable_core_dumps()` also makes the process non-dumpable.
+    #[cfg(target_os = "freebsd")]
+    pub fn disable_tracing(mut self) -> Self {
+        self.disable_tracing = true;
+        self
+    }
+
+    /// After switching to a non-root user, choose which processes can trace it with
+    /// `PR_SET_PTRACER` (Linux only)
+    ///
+    /// Changing the user makes the process non-dumpable, so it is made dumpable again unless
+    /// `Ptracer::Restricted` is used. For that reason, allowing a tracer can't be combined with
+    /// `disable_core_dumps()`.
+    pub fn ptracer(mut self, ptracer: Ptracer) -> Self {
+        self.ptracer = Some(ptracer);
+        self
+    }
+
+    /// Before switching to a non-root user, confine the process to a routing table and its
+    /// rdomain using `setrtable()` (OpenBSD only)
+    #[cfg(target_os = "openbsd")]
+    pub fn routing_table(mut self, rtable: libc::c_int) -> Self {
+        self.routing_table = Some(rtable);
+        self
+    }
+
+    /// Like `chroot()`, but reject empty and relative paths, and paths containing NUL bytes
+    #[cfg(feature = "chroot")]
+    pub fn try_chroot<T: AsRef<Path>>(self, path: T) -> Result<Self, PrivDropError> {
+        let path = path.as_ref();
+        if !path.is_absolute() || path.as_os_str().as_bytes().contains(&0) {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "The chroot path must be an absolute path",
+            )));
+        }
+        Ok(self.chroot(path))
+    }
+
+    /// Like `user()`, but reject empty names and names containing NUL bytes
+    pub fn try_user<S: AsRef<OsStr>>(self, user: S) -> Result<Self, PrivDropError> {
+        validate_name(user.as_ref(), "Invalid username")?;
+        Ok(self.user(user))
+    }
+
+    /// Like `group()`, but reject empty names and names containing NUL bytes
+    pub fn try_group<S: AsRef<OsStr>>(self, group: S) -> Result<Self, PrivDropError> {
+        validate_name(group.as_ref(), "Invalid group name")?;
+        Ok(self.group(group))
+    }
+
+    /// Like `group_list()`, but reject empty names and names containing NUL bytes
+    #[cfg(feature = "groups")]
+    pub fn try_group_list<S: AsRef<OsStr>>(self, group_list: &[S]) -> Result<Self, PrivDropError> {
+        for group in group_list {
+            validate_name(group.as_ref(), "Invalid group name")?;
+        }
+        Ok(self.group_list(group_list))
+    }
+
+    /// When applying the changes fails, inspect the environment and attach a hint on how to
+    /// fix the problem to the error
+    pub fn explain_failures(mut self) -> Self {
+        self.explain_failures = true;
+        self
+    }
+
+    /// Set the list of supplementary groups using numeric IDs, without any name lookups
+    ///
+    /// These groups are added to the ones set with `group_list()`.
+    #[cfg(feature = "groups")]
+    pub fn group_id_list(mut self, group_id_list: &[libc::gid_t]) -> Self {
+        self.group_id_list = Some(group_id_list.to_vec());
+        self
+    }
+
+    /// Set what to do when there are more supplementary groups than `NGROUPS_MAX`
+    #[cfg(feature = "groups")]
+    pub fn group_limit_policy(mut self, policy: GroupLimitPolicy) -> Self {
+        self.group_limit_policy = policy;
+        self
+    }
+
+    /// Before applying the changes, move the process to a new UTS namespace, and set a
+    /// hostname that is only visible there (Linux only)
+    pub fn hostname<S: AsRef<OsStr>>(mut self, hostname: S) -> Self {
+        self.hostname = Some(hostname.as_ref().to_owned());
+        self
+    }
+
+    /// Once the changes have been applied, write a single newline to the given file descriptor,
+    /// or increment its counter if it is an eventfd
+    ///
+    /// This lets a supervisor waiting on a pipe or an eventfd know that the process is now
+    /// unprivileged. The descriptor is left open.
+    pub fn notify_fd(mut self, fd: RawFd) -> Self {
+        self.notify_fd = Some(fd);
+        self
+    }
+
     /// Set the full list of groups to switch to
+    #[cfg(feature = "groups")]
     pub fn group_list<S: AsRef<OsStr>>(mut self, group_list: &[S]) -> Self {
         self.group_list = Some(group_list.iter().map(|x| x.as_ref().to_owned()).collect());
         self
     }
 
     /// Apply the changes
-    pub fn apply(self) -> Result<(), PrivDropError> {
-        Self::preload()?;
-        let ids = self.lookup_ids()?;
-        self.do_chroot()?.do_idchange(ids)?;
-        Ok(())
+    ///
+    /// The configuration is left untouched, so it can be applied again, e.g. in multiple forked workers.
+    ///
+    /// Names are resolved and all the checks are performed before the process is modified.
+    /// Directories, files and the prefetch step are then handled with the initial privileges.
+    /// The first irreversible step is the point of no return: detaching from the terminal for
+    /// `controlling_tty()`, entering a new UTS namespace for `hostname()`, changing the groups
+    /// when `order()` puts them first, or else changing the root directory. If a later step
+    /// fails, the process is left partially confined and should exit.
+    pub fn apply(&self) -> Result<(), PrivDropError> {
+        self.apply_with_report()
+            .map(|report| std::mem::forget(report.ephemeral_lock))
+    }
+
+    /// Apply the changes, and return a report of the resulting credentials
+    pub fn apply_with_report(&self) -> Result<AppliedPrivileges, PrivDropError> {
+        self.apply_with_files().map(|(report, _)| report)
+    }
+
+    /// Apply the changes, and return the files declared with `open_before_drop()` and
+    /// `append_before_drop()` along with the report
+    ///
+    /// The files are opened with the initial privileges, before changing the root directory,
+    /// and are returned in the order they were declared.
+    pub fn apply_with_files(&self) -> Result<(AppliedPrivileges, Vec<File>), PrivDropError> {
+        let res = Self::preload().and_then(|_| {
+            let start = Instant::now();
+            let resolved = self.resolve()?;
+            self.apply_resolved(resolved, start.elapsed(), || self.open_files())
+        });
+        res.map_err(|e| self.explain(e))
+    }
+
+    /// Apply the changes, running `prefetch` with the initial privileges right before
+    ///
+    /// `prefetch` runs after names have been resolved and checks have been performed, but
+    /// before the root directory and credentials are changed. Its return value, e.g. bound
+    /// sockets or loaded keys, is returned along with the report.
+    pub fn apply_with_prefetch<T, F: FnOnce() -> T>(
+        &self,
+        prefetch: F,
+    ) -> Result<(AppliedPrivileges, T), PrivDropError> {
+        let res = Self::preload().and_then(|_| {
+            let start = Instant::now();
+            let resolved = self.resolve()?;
+            self.apply_resolved(resolved, start.elapsed(), || Ok(prefetch()))
+        });
+        res.map_err(|e| self.explain(e))
     }
 
-    fn preload() -> Result<(), PrivDropError> {
-        let c_locale = CString::new("C").unwrap();
-        unsafe {
-            libc::strerror(1);
-            libc::setlocale(libc::LC_CTYPE, c_locale.as_ptr());
-            libc::setlocale(libc::LC_COLLATE, c_locale.as_ptr());
-            let mut now: libc::time_t = 0;
-            libc::time(&mut now);
-            libc::localtime(&now);
+    pub(crate) fn explain(&self, e: PrivDropError) -> PrivDropError {
+        if !self.explain_failures {
+            return e;
         }
-        Ok(())
+        #[cfg(feature = "chroot")]
+        let chroot = self.chroot.as_deref();
+        #[cfg(not(feature = "chroot"))]
+        let chroot = None;
+        e.with_hint(diagnostics::explain(chroot))
     }
 
-    fn uidcheck() -> Result<(), PrivDropError> {
-        if !unistd::geteuid().is_root() {
-            Err(PrivDropError::from((
-                ErrorKind::SysError,
-                "Starting this application requires root privileges",
-            )))
+    pub(crate) fn open_files(&self) -> Result<Vec<File>, PrivDropError> {
+        let mut files = Vec::with_capacity(self.files.len());
+        for (path, append) in &self.files {
+            let file = OpenOptions::new()
+                .read(!append)
+                .append(*append)
+                .create(*append)
+                .open(path)
+                .map_err(|_| {
+                    PrivDropError::from((
+                        ErrorKind::SysError,
+                        "Unable to open a file before dropping privileges",
+                    ))
+                })?;
+            files.push(file);
+        }
+        Ok(files)
+    }
+
+    /// Apply credentials previously resolved from this configuration
+    ///
+    /// `prefetch` runs right before the changes are applied, and its result is returned along
+    /// with the report.
+    pub(crate) fn apply_resolved<T, F: FnOnce() -> Result<T, PrivDropError>>(
+        &self,
+        mut resolved: ResolvedPrivDrop,
+        resolution: Duration,
+        prefetch: F,
+    ) -> Result<(AppliedPrivileges, T), PrivDropError> {
+        let (ephemeral_lock, executable_stats) = self.prepare(&mut resolved, false)?;
+        let prefetched = prefetch()?;
+        let mut progress = PartialApplication::default();
+        let res = match self.sandbox() {
+            Some((ns_uid, ns_gid)) => resolved.apply_sandboxed(ns_uid, ns_gid, &mut progress),
+            None => resolved.apply_checked(&mut progress),
+        }
+        .and_then(|mut timings| {
+            timings.resolution = resolution;
+            if let Some(executable_stats) = executable_stats {
+                audit::check_executable_not_writable(&executable_stats)?;
+            }
+            self.check_applied_chroot(&resolved)?;
+            let report = AppliedPrivileges::current(&resolved, timings)?;
+            Ok(AppliedPrivileges {
+                ephemeral_lock,
+                ..report
+            })
+        });
+        self.report_failure(&res, &progress);
+        Ok((res?, prefetched))
+    }
+
+    /// Perform the checks and the preparation that precede applying resolved credentials
+    ///
+    /// This allocates the ephemeral ID, counts the processes for `forbid_fork()`, checks that
+    /// the changes can be applied, and provisions the directories. If `forked` is set, the
+    /// changes are going to be applied by a child that hasn't been forked yet. The ephemeral
+    /// ID lock is returned, along with what the executable check needs after the changes.
+    pub(crate) fn prepare(
+        &self,
+        resolved: &mut ResolvedPrivDrop,
+        forked: bool,
+    ) -> Result<(Option<EphemeralLock>, Option<audit::ExecutableStats>), PrivDropError> {
+        let ephemeral_lock = self.allocate_ephemeral_id(resolved)?;
+        if self.forbid_fork {
+            let uid = resolved.uid.unwrap_or_else(|| unsafe { libc::getuid() });
+            resolved.max_processes = Some(resources::count_processes(uid, forked)?);
+        }
+        if self.sandbox().is_some() {
+            Self::check_sandbox(resolved)?;
+        } else {
+            Restrictions::detect().check(resolved, self.adapt_to_restrictions)?;
+        }
+        resolved.check()?;
+        if let Some(ref sensitive_paths) = self.sensitive_paths {
+            audit::check_writable_fds(sensitive_paths)?;
+        }
+        #[cfg(feature = "chroot")]
+        self.audit_chroot_contents(resolved.chroot_path())?;
+        self.check_home_dir(resolved)?;
+        self.provision_dirs(resolved)?;
+        let executable_stats = if self.check_executable_not_writable {
+            Some(audit::executable_stats()?)
         } else {
-            Ok(())
+            None
+        };
+        Ok((ephemeral_lock, executable_stats))
+    }
+
+    /// Apply credentials checked by `prepare()` right after `fork()`, only performing the
+    /// system calls
+    pub(crate) fn apply_prepared(&self, resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        let mut progress = PartialApplication::default();
+        let res = resolved
+            .apply_checked(&mut progress)
+            .and_then(|_| self.check_applied_chroot(resolved));
+        self.report_failure(&res, &progress);
+        res
+    }
+
+    /// Check that the chroot can't be escaped once the changes have been applied, if enabled
+    #[cfg(feature = "chroot")]
+    fn check_applied_chroot(&self, resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        if self.check_chroot_escape && resolved.has_chroot() {
+            audit::check_chroot_escape()?;
         }
+        Ok(())
     }
 
-    fn do_chroot(mut self) -> Result<Self, PrivDropError> {
-        if let Some(chroot) = self.chroot.take() {
-            Self::uidcheck()?;
-            unistd::chdir(&chroot)?;
-            unistd::chroot(&chroot)?;
-            unistd::chdir("/")?
+    #[cfg(not(feature = "chroot"))]
+    fn check_applied_chroot(&self, _resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        Ok(())
+    }
+
+    /// Call the `on_failure()` hook if applying the changes failed after some were made
+    fn report_failure<T>(&self, res: &Result<T, PrivDropError>, progress: &PartialApplication) {
+        if let (Err(e), Some(hook)) = (res, &self.on_failure) {
+            if !progress.is_empty() {
+                (hook.0)(e, progress);
+            }
         }
-        Ok(self)
     }
 
-    fn lookup_user(
-        user: &OsStr,
-        fallback_to_ids_if_names_are_numeric: bool,
-    ) -> Result<UserIds, PrivDropError> {
-        let username = CString::new(user.as_bytes())
-            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid username")))?;
-        let mut pwd = unsafe { std::mem::zeroed::<libc::passwd>() };
-        let mut pwbuf = vec![0; 4096];
-        let mut pwent = std::ptr::null_mut::<libc::passwd>();
-        let ret = unsafe {
-            libc::getpwnam_r(
-                username.as_ptr(),
-                &mut pwd,
-                pwbuf.as_mut_ptr(),
-                pwbuf.len(),
-                &mut pwent,
-            )
+    /// Pick the ephemeral ID to switch to, if enabled
+    ///
+    /// This is only done when the changes are applied, so that resolving the configuration,
+    /// e.g. in `preflight()` or `fingerprint()`, neither allocates nor locks an ID.
+    fn allocate_ephemeral_id(
+        &self,
+        resolved: &mut ResolvedPrivDrop,
+    ) -> Result<Option<EphemeralLock>, PrivDropError> {
+        let range = match self.ephemeral_ids {
+            Some(ref range) => range,
+            None => return Ok(None),
         };
+        let (id, lock) = ephemeral::allocate(range, self.ephemeral_lock_dir.as_deref())?;
+        resolved.uid = Some(id);
+        resolved.gid = Some(id);
+        resolved.groups = vec![id];
+        Ok(lock)
+    }
+
+    /// ID mappings of the unprivileged sandbox, if enabled
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn sandbox(&self) -> Option<(libc::uid_t, libc::gid_t)> {
+        self.unprivileged_sandbox
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn sandbox(&self) -> Option<(libc::uid_t, libc::gid_t)> {
+        None
+    }
+
+    /// Reject the changes that can't be made in unprivileged sandbox mode
+    ///
+    /// The privileges of the process outside the namespace don't matter in that mode, so the
+    /// environment restrictions are not checked.
+    fn check_sandbox(resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        if resolved.uid.is_some() || resolved.gid.is_some() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Users and groups can't be changed in unprivileged sandbox mode",
+            )));
+        }
+        if !resolved.groups.is_empty() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Supplementary groups can't be set in unprivileged sandbox mode, as setgroups() is denied in the user namespace",
+            )));
+        }
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        if !resolved.keep_caps.is_empty() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Capabilities can't be retained in unprivileged sandbox mode",
+            )));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "chroot")]
+    fn audit_chroot_contents(&self, chroot: Option<&Path>) -> Result<(), PrivDropError> {
+        if let (Some(chroot), Some((max_depth, max_entries))) = (chroot, self.chroot_audit_limits) {
+            audit::check_chroot_contents(chroot, max_depth, max_entries)?;
+        }
+        Ok(())
+    }
 
-        if ret != 0 || pwent.is_null() {
-            if !fallback_to_ids_if_names_are_numeric {
-                return Err(PrivDropError::from((ErrorKind::SysError, "User not found")));
+    /// Check that the changes can be applied, without changing anything
+    ///
+    /// This resolves all the names, checks that the process has the required privileges,
+    /// that the chroot directory exists and passes the configured checks, and that the
+    /// number of supplementary groups is within the system limit.
+    pub fn preflight(&self) -> Result<(), PrivDropError> {
+        let mut resolved = self.resolve()?;
+        Restrictions::detect().check(&mut resolved, self.adapt_to_restrictions)?;
+        resolved.check()?;
+        #[cfg(feature = "chroot")]
+        self.audit_chroot_contents(resolved.chroot_path())?;
+        self.check_home_dir(&resolved)?;
+        Ok(())
+    }
+
+    fn check_home_dir(&self, resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        if !self.audit_home_dir {
+            return Ok(());
+        }
+        match (&self.user, resolved.uid) {
+            (Some(user), Some(uid)) => audit::check_home_dir(user, uid),
+            _ => Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "The home directory can only be audited when switching to a named user",
+            ))),
+        }
+    }
+
+    /// Like `preflight()`, but report all the problems found instead of only the first one
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let numeric_names = self.numeric_names();
+        let mut resolver = Resolver::new();
+        if let Some(ref user) = self.user {
+            errors.check(Self::lookup_user(&mut resolver, user, numeric_names));
+        }
+        if let Some(ref group) = self.group {
+            errors.check(Self::lookup_group(&mut resolver, group, numeric_names));
+        }
+        #[cfg(feature = "groups")]
+        if let Some(ref group_list) = self.group_list {
+            for group in group_list {
+                errors.check(Self::lookup_group(&mut resolver, group, numeric_names));
             }
-            let user_str = user.to_str().ok_or_else(|| {
-                PrivDropError::from((
+        }
+        #[cfg(feature = "chroot")]
+        if let Some(ref chroot) = self.chroot {
+            if chroot.is_dir() {
+                errors.check(self.audit_chroot_contents(Some(chroot)));
+            } else {
+                errors.push(PrivDropError::from((
                     ErrorKind::SysError,
-                    "User not found and username is not a valid number",
-                ))
-            })?;
-            let uid = user_str.parse().map_err(|_| {
+                    "The chroot directory doesn't exist",
+                )));
+            }
+        }
+        if errors.is_empty() {
+            errors.check(self.preflight());
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(())
+    }
+
+    /// Create the runtime, state and cache directories, owned by the target user
+    pub(crate) fn provision_dirs(&self, resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        let directories = [&self.runtime_dir, &self.state_dir, &self.cache_dir];
+        for (path, mode) in directories.iter().filter_map(|x| x.as_ref()) {
+            dirs::provision(path, *mode, resolved.uid, resolved.gid)?;
+        }
+        Ok(())
+    }
+
+    /// Compute a stable fingerprint of the target credentials
+    ///
+    /// See `ResolvedPrivDrop::fingerprint()`.
+    pub fn fingerprint(&self) -> Result<u64, PrivDropError> {
+        Ok(self.resolve()?.fingerprint())
+    }
+
+    /// Look up all the names and compute the final set of credentials, without applying them
+    ///
+    /// The returned `ResolvedPrivDrop` can be applied later without performing any lookups.
+    pub fn resolve(&self) -> Result<ResolvedPrivDrop, PrivDropError> {
+        self.resolve_with(&mut Resolver::new())
+    }
+
+    /// Like `resolve()`, reusing the buffers and the records cached by `resolver`
+    pub fn resolve_with(&self, resolver: &mut Resolver) -> Result<ResolvedPrivDrop, PrivDropError> {
+        self.check_option_conflicts()?;
+        if self.disable_core_dumps
+            && matches!(self.ptracer, Some(Ptracer::Process(_)) | Some(Ptracer::Any))
+        {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Allowing a ptracer requires the process to be dumpable, so core dumps can't be disabled",
+            )));
+        }
+        let ids = self.lookup_ids(resolver)?;
+        if let (true, Some(user)) = (self.reject_expired_account, &self.user) {
+            account::check_not_expired(user)?;
+        }
+        if let (Some(min_uid), Some(uid)) = (self.min_target_uid, ids.uid) {
+            if uid < min_uid && !self.allowed_target_uids.contains(&uid) {
+                return Err(PrivDropError::from((
+                    ErrorKind::InvalidConfig,
+                    "The target user ID is below the configured minimum",
+                )));
+            }
+        }
+
+        let mut groups = vec![];
+        #[cfg(feature = "groups")]
+        let mut initgroups_user = None;
+        #[cfg(feature = "groups")]
+        {
+            if self.include_default_supplementary_groups {
+                let (user, gid) = match (ids.name.as_ref().or(self.user.as_ref()), ids.gid) {
+                    (Some(user), Some(gid)) => (user, gid),
+                    _ => {
+                        return Err(PrivDropError::from((
+                            ErrorKind::InvalidConfig,
+                            "Unable to determine default supplementary groups without a user name and a base gid",
+                        )))
+                    }
+                };
+                if self.use_initgroups {
+                    initgroups_user = Some(CString::new(user.as_bytes()).map_err(|_| {
+                        PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username"))
+                    })?);
+                } else {
+                    groups.extend(GroupSet::for_user(user, gid)?.into_ids());
+                }
+            }
+            if let Some(ref other_user) = self.supplementary_groups_from_user {
+                let other_ids = Self::lookup_user(resolver, other_user, self.numeric_names())?;
+                let other_gid = other_ids.gid.ok_or_else(|| {
+                    PrivDropError::from((
+                        ErrorKind::InvalidConfig,
+                        "Unable to determine the supplementary groups of an unknown user",
+                    ))
+                })?;
+                let other_user = other_ids.name.as_ref().unwrap_or(other_user);
+                groups.extend(GroupSet::for_user(other_user, other_gid)?.into_ids());
+            }
+            if !self.excluded_groups.is_empty()
+                || !self.excluded_group_ids.is_empty()
+                || self.allowed_groups.is_some()
+            {
+                let excluded_groups = self
+                    .excluded_groups
+                    .iter()
+                    .map(|group| Self::lookup_group(resolver, group, self.numeric_names()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                groups.retain(|group| !excluded_groups.contains(group));
+            }
+            if let Some(ref group_list) = ids.group_list {
+                groups.extend(group_list.iter().cloned());
+            }
+        }
+        if let Some(gid) = ids.gid {
+            groups.push(gid);
+        }
+        #[cfg(feature = "groups")]
+        let (unique_groups, truncated_groups) = {
+            let mut unique_groups = GroupSet::from_ids(groups);
+            unique_groups.retain(|group| {
+                Some(group) == ids.gid
+                    || !self
+                        .excluded_group_ids
+                        .iter()
+                        .any(|range| range.contains(&group))
+            });
+            if let Some(ref allowed_groups) = self.allowed_groups {
+                let allowed_groups = allowed_groups
+                    .iter()
+                    .map(|group| Self::lookup_group(resolver, group, self.numeric_names()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if let Some(gid) = ids.gid {
+                    if !allowed_groups.contains(&gid) {
+                        return Err(PrivDropError::from((
+                            ErrorKind::InvalidConfig,
+                            "The primary group is not in the list of allowed groups",
+                        )));
+                    }
+                }
+                unique_groups.retain(|group| allowed_groups.contains(&group));
+            }
+            let truncated_groups = unique_groups.limit(
+                ids.gid,
+                ids.group_list.as_deref().unwrap_or_default(),
+                self.group_limit_policy,
+            );
+            (unique_groups.into_ids(), truncated_groups)
+        };
+        // Without supplementary group management, the only group is the primary group.
+        #[cfg(not(feature = "groups"))]
+        let (unique_groups, truncated_groups) = (groups, vec![]);
+
+        #[cfg(feature = "chroot")]
+        let chroot = match self.chroot {
+            Some(ref chroot) => {
+                Some(CString::new(chroot.as_os_str().as_bytes()).map_err(|_| {
+                    PrivDropError::from((ErrorKind::InvalidConfig, "Invalid chroot path"))
+                })?)
+            }
+            None => None,
+        };
+        #[cfg(feature = "chroot")]
+        let chroot = if self.chroot_into_users_home {
+            let home = match self.user {
+                Some(ref user) => resolver.home_dir(user)?,
+                None => None,
+            }
+            .filter(|home| !home.is_empty())
+            .ok_or_else(|| {
                 PrivDropError::from((
                     ErrorKind::SysError,
-                    "User not found and username is not a valid number",
+                    "Unable to determine the home directory of the target user",
                 ))
             })?;
-            return Ok(UserIds {
-                uid: Some(uid),
-                gid: None,
-                group_list: None,
-            });
+            Some(CString::new(home.as_bytes()).map_err(|_| {
+                PrivDropError::from((ErrorKind::InvalidConfig, "Invalid home directory"))
+            })?)
+        } else {
+            chroot
+        };
+        #[cfg(feature = "chroot")]
+        if self.chroot_overlay && chroot.is_none() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "An overlay chroot requires a chroot path",
+            )));
+        }
+        #[cfg(feature = "chroot")]
+        if let (true, Some(chroot)) = (self.validate_chroot, &chroot) {
+            chroot::validate(chroot)?;
         }
 
-        let uid = unsafe { *pwent }.pw_uid;
-        let gid = unsafe { *pwent }.pw_gid;
+        let chdir = match self.runtime_dir {
+            Some((ref path, _)) if self.chdir_to_runtime_dir => {
+                Some(CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+                    PrivDropError::from((ErrorKind::InvalidConfig, "Invalid runtime directory"))
+                })?)
+            }
+            _ => None,
+        };
+        #[cfg(feature = "chroot")]
+        let chdir = match self.chroot_workdir {
+            Some(ref workdir) => {
+                let chroot_fd = self.chroot_fd.as_ref().map(|fd| fd.as_raw_fd());
+                Some(chroot::workdir(chroot.as_deref(), chroot_fd, workdir)?)
+            }
+            None => chdir,
+        };
+
+        Ok(ResolvedPrivDrop {
+            #[cfg(feature = "chroot")]
+            chroot,
+            #[cfg(feature = "chroot")]
+            chroot_fd: self.chroot_fd.clone(),
+            #[cfg(feature = "chroot")]
+            chroot_overlay: self.chroot_overlay,
+            #[cfg(feature = "chroot")]
+            groups_before_chroot: self.order == StepOrder::GroupsFirst,
+            mount_propagation: self.mount_propagation,
+            uid: ids.uid,
+            gid: ids.gid,
+            groups: unique_groups,
+            truncated_groups,
+            skip_setgroups: false,
+            deny_setuid_family: self.deny_setuid_family,
+            clear_saved_ids: self.clear_saved_ids,
+            no_new_privs: self.no_new_privs,
+            disable_core_dumps: self.disable_core_dumps,
+            umask: self.umask,
+            clear_env: self.clear_env,
+            cloexec_fds: self.cloexec_fds,
+            lock_securebits: self.lock_securebits,
+            disabled_speculation: self.disabled_speculation.clone(),
+            disable_tracing: self.disable_tracing,
+            ptracer: self.ptracer,
+            max_processes: self.max_processes,
+            chdir,
+            detach_tty: self.controlling_tty == ControllingTty::Detach,
+            cpu_affinity: self.cpu_affinity.clone(),
+            sched_policy: self.sched_policy,
+            routing_table: self.routing_table,
+            hostname: self
+                .hostname
+                .as_ref()
+                .map(|hostname| hostname.as_bytes().to_vec()),
+            notify_fd: self.notify_fd,
+            #[cfg(feature = "groups")]
+            initgroups_user,
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            keep_caps: capabilities::parse_caps(&self.keep_caps)?,
+        })
+    }
+
+    pub(crate) fn preload() -> Result<(), PrivDropError> {
+        // Load the error messages, locale and timezone data, which may not be reachable any
+        // more after a chroot.
+        #[cfg(feature = "preload")]
+        {
+            let c_locale = CString::new("C").unwrap();
+            unsafe {
+                libc::strerror(1);
+                libc::setlocale(libc::LC_CTYPE, c_locale.as_ptr());
+                libc::setlocale(libc::LC_COLLATE, c_locale.as_ptr());
+                let mut now: libc::time_t = 0;
+                libc::time(&mut now);
+                libc::localtime(&now);
+            }
+        }
+        // Draw random numbers once, so that the random number generators are initialized
+        // while the random device is still reachable.
+        let _ = std::collections::hash_map::RandomState::new();
+        #[cfg(any(
+            target_os = "freebsd",
+            target_os = "dragonfly",
+            target_os = "openbsd",
+            target_os = "netbsd",
+            target_os = "macos",
+            target_os = "ios"
+        ))]
+        {
+            let mut buf = [0u8; 16];
+            unsafe { libc::arc4random_buf(buf.as_mut_ptr() as *mut _, buf.len()) };
+        }
+        #[cfg(feature = "backtrace")]
+        {
+            // Capturing and formatting a backtrace loads and caches the symbol data of the
+            // executable, which may not be reachable any more after a chroot.
+            let backtrace = std::backtrace::Backtrace::force_capture();
+            let _ = backtrace.to_string();
+        }
+        Ok(())
+    }
+
+    fn lookup_user(
+        resolver: &mut Resolver,
+        user: &OsStr,
+        numeric_names: Option<NumericNamePolicy>,
+    ) -> Result<UserIds, PrivDropError> {
+        if user.as_bytes().contains(&0) {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Invalid username",
+            )));
+        }
+
+        let account = match resolver.user(user) {
+            Err(e) if e.kind() == ErrorKind::LookupLimit => return Err(e),
+            Ok(Some((uid, _))) if prefer_numeric_id(user, uid, numeric_names)? => None,
+            account => account.ok().flatten(),
+        };
+        let (uid, gid) = match account {
+            Some(ids) => ids,
+            None => {
+                #[cfg(target_os = "android")]
+                if let Some(id) = user.to_str().and_then(android::id_from_name) {
+                    return Ok(UserIds {
+                        uid: Some(id),
+                        gid: Some(id),
+                        #[cfg(feature = "groups")]
+                        group_list: None,
+                        #[cfg(feature = "groups")]
+                        name: None,
+                    });
+                }
+                if numeric_names.is_none() {
+                    return Err(PrivDropError::from((ErrorKind::NotFound, "User not found")));
+                }
+                let uid = parse_id(user).ok_or_else(|| {
+                    PrivDropError::from((
+                        ErrorKind::NotFound,
+                        "User not found and username is not a valid number",
+                    ))
+                })?;
+                // The account may still exist, in which case it provides the primary group and
+                // the name required to look up the default supplementary groups.
+                let account = match resolver.user_by_uid(uid) {
+                    Err(e) if e.kind() == ErrorKind::LookupLimit => return Err(e),
+                    account => account.ok().flatten(),
+                };
+                #[cfg(feature = "groups")]
+                let name = account.as_ref().map(|(name, _)| name.clone());
+                return Ok(UserIds {
+                    uid: Some(uid),
+                    gid: account.map(|(_, gid)| gid),
+                    #[cfg(feature = "groups")]
+                    group_list: None,
+                    #[cfg(feature = "groups")]
+                    name,
+                });
+            }
+        };
 
         Ok(UserIds {
             uid: Some(uid),
             gid: Some(gid),
+            #[cfg(feature = "groups")]
             group_list: None,
+            #[cfg(feature = "groups")]
+            name: None,
         })
     }
 
-    fn default_group_list(
-        user: &OsStr,
-        gid: libc::gid_t,
-    ) -> Result<Option<Vec<libc::gid_t>>, PrivDropError> {
-        let username = CString::new(user.as_bytes())
-            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid username")))?;
-        let mut groups = vec![0; 256];
-        let mut ngroups = groups.len() as _;
-        let ret = unsafe {
-            libc::getgrouplist(
-                username.as_ptr(),
-                gid as _,
-                groups.as_mut_ptr(),
-                &mut ngroups,
-            )
-        };
-        if ret == -1 {
-            return Ok(None);
+    pub(crate) fn lookup_group(
+        resolver: &mut Resolver,
+        group: &OsStr,
+        numeric_names: Option<NumericNamePolicy>,
+    ) -> Result<libc::gid_t, PrivDropError> {
+        if group.as_bytes().contains(&0) {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Invalid group name",
+            )));
         }
-        groups.truncate(ngroups as _);
-        let mut groups_ = Vec::with_capacity(groups.len());
-        for group in groups {
-            groups_.push(group as _);
+
+        let gid = match resolver.group(group) {
+            Err(e) if e.kind() == ErrorKind::LookupLimit => return Err(e),
+            Ok(Some(gid)) if prefer_numeric_id(group, gid, numeric_names)? => None,
+            gid => gid.ok().flatten(),
+        };
+        match gid {
+            Some(gid) => Ok(gid),
+            None => {
+                #[cfg(target_os = "android")]
+                if let Some(id) = group.to_str().and_then(android::id_from_name) {
+                    return Ok(id);
+                }
+                if numeric_names.is_none() {
+                    return Err(PrivDropError::from((
+                        ErrorKind::NotFound,
+                        "Group not found",
+                    )));
+                }
+                parse_id(group).ok_or_else(|| {
+                    PrivDropError::from((
+                        ErrorKind::NotFound,
+                        "Group not found and group is not a valid number",
+                    ))
+                })
+            }
         }
-        Ok(Some(groups_))
     }
 
-    fn lookup_group(
-        group: &OsStr,
-        fallback_to_ids_if_names_are_numeric: bool,
-    ) -> Result<libc::gid_t, PrivDropError> {
-        let groupname = CString::new(group.as_bytes())
-            .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Invalid group name")))?;
-
-        let mut grp = unsafe { std::mem::zeroed::<libc::group>() };
-        let mut grbuf = vec![0; 4096];
-        let mut grent = std::ptr::null_mut::<libc::group>();
-        let ret = unsafe {
-            libc::getgrnam_r(
-                groupname.as_ptr(),
-                &mut grp,
-                grbuf.as_mut_ptr(),
-                grbuf.len(),
-                &mut grent,
-            )
+    /// Look up a list of groups, concurrently if it is long, as each lookup may require a
+    /// round-trip to a directory server
+    #[cfg(feature = "groups")]
+    fn lookup_groups(
+        resolver: &mut Resolver,
+        groups: &[OsString],
+        numeric_names: Option<NumericNamePolicy>,
+    ) -> Result<Vec<libc::gid_t>, PrivDropError> {
+        let lookup_chunk = |resolver: &mut Resolver, chunk: &[OsString]| {
+            chunk
+                .iter()
+                .map(|group| Self::lookup_group(resolver, group, numeric_names))
+                .collect::<Result<Vec<_>, _>>()
         };
+        if groups.len() < PARALLEL_LOOKUPS_MIN_GROUPS {
+            return lookup_chunk(resolver, groups);
+        }
+        // Each thread uses its own resolver, whose records are merged into the shared one.
+        let lookups = std::thread::scope(|scope| {
+            let lookups: Vec<_> = groups
+                .chunks(groups.len().div_ceil(MAX_PARALLEL_LOOKUPS))
+                .map(|chunk| {
+                    let mut thread_resolver = resolver.clone();
+                    let handle = std::thread::Builder::new()
+                        .spawn_scoped(scope, move || {
+                            let gids = lookup_chunk(&mut thread_resolver, chunk);
+                            (thread_resolver, gids)
+                        })
+                        .ok();
+                    (chunk, handle)
+                })
+                .collect();
+            lookups
+                .into_iter()
+                .map(|(chunk, handle)| (chunk, handle.map(|handle| handle.join())))
+                .collect::<Vec<_>>()
+        });
+        let mut gids = Vec::with_capacity(groups.len());
+        for (chunk, lookup) in lookups {
+            let chunk_gids = match lookup {
+                Some(Ok((thread_resolver, chunk_gids))) => {
+                    resolver.merge(thread_resolver);
+                    chunk_gids?
+                }
+                Some(Err(_)) => {
+                    return Err(PrivDropError::from((
+                        ErrorKind::SysError,
+                        "Group lookup thread panicked",
+                    )))
+                }
+                None => lookup_chunk(resolver, chunk)?,
+            };
+            gids.extend(chunk_gids);
+        }
+        Ok(gids)
+    }
 
-        if ret != 0 || grent.is_null() {
-            if !fallback_to_ids_if_names_are_numeric {
-                return Err(PrivDropError::from((
-                    ErrorKind::SysError,
-                    "Group not found",
-                )));
+    /// Return `true` if the option set by the setter called `name` is set
+    pub(crate) fn has_option(&self, name: &str) -> bool {
+        match name {
+            "user" => self.user.is_some(),
+            "group" => self.group.is_some(),
+            "ephemeral_ids" => self.ephemeral_ids.is_some(),
+            "user_from_path_owner" => self.path_owner.is_some(),
+            "forbid_fork" => self.forbid_fork,
+            "chdir_to_runtime_dir" => self.chdir_to_runtime_dir,
+            #[cfg(feature = "chroot")]
+            "chroot" => self.chroot.is_some(),
+            #[cfg(feature = "chroot")]
+            "chroot_fd" => self.chroot_fd.is_some(),
+            #[cfg(feature = "chroot")]
+            "chroot_into_users_home" => self.chroot_into_users_home,
+            #[cfg(feature = "chroot")]
+            "chroot_overlay" => self.chroot_overlay,
+            #[cfg(feature = "chroot")]
+            "validate_chroot" => self.validate_chroot,
+            #[cfg(feature = "chroot")]
+            "audit_chroot" => self.chroot_audit_limits.is_some(),
+            #[cfg(feature = "chroot")]
+            "check_chroot_escape" => self.check_chroot_escape,
+            #[cfg(feature = "groups")]
+            "use_initgroups" => self.use_initgroups,
+            #[cfg(feature = "groups")]
+            "exclude_groups" => !self.excluded_groups.is_empty(),
+            #[cfg(feature = "groups")]
+            "exclude_group_ids" => !self.excluded_group_ids.is_empty(),
+            #[cfg(feature = "groups")]
+            "restrict_groups_to" => self.allowed_groups.is_some(),
+            #[cfg(feature = "groups")]
+            "group_list" => self.group_list.is_some(),
+            #[cfg(feature = "groups")]
+            "group_id_list" => self.group_id_list.is_some(),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            "unprivileged_sandbox" => self.unprivileged_sandbox.is_some(),
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            "keep_caps" => !self.keep_caps.is_empty(),
+            #[cfg(all(feature = "caps", target_os = "linux"))]
+            "keep_net_bind_service" => self.keep_caps.iter().any(|x| x == "CAP_NET_BIND_SERVICE"),
+            _ => false,
+        }
+    }
+
+    /// Reject the combinations of options listed in `OPTION_CONFLICTS`
+    fn check_option_conflicts(&self) -> Result<(), PrivDropError> {
+        for &(a, b, message) in OPTION_CONFLICTS.iter() {
+            if self.has_option(a) && self.has_option(b) {
+                return Err(PrivDropError::from((ErrorKind::InvalidConfig, message)));
             }
-            let group_str = group.to_str().ok_or_else(|| {
-                PrivDropError::from((
-                    ErrorKind::SysError,
-                    "Group not found and group is not a valid number",
-                ))
-            })?;
-            let gid: libc::gid_t = group_str.parse().map_err(|_| {
-                PrivDropError::from((
-                    ErrorKind::SysError,
-                    "Group not found and group is not a valid number",
-                ))
-            })?;
-            return Ok(gid);
         }
+        Ok(())
+    }
 
-        Ok(unsafe { *grent }.gr_gid)
+    fn numeric_names(&self) -> Option<NumericNamePolicy> {
+        if self.fallback_to_ids_if_names_are_numeric {
+            Some(self.numeric_name_policy)
+        } else {
+            None
+        }
     }
 
-    fn lookup_ids(&self) -> Result<UserIds, PrivDropError> {
+    fn lookup_ids(&self, resolver: &mut Resolver) -> Result<UserIds, PrivDropError> {
         let mut ids = UserIds::default();
 
+        if let Some(ref path) = self.path_owner {
+            let metadata = std::fs::metadata(path).map_err(|_| {
+                PrivDropError::from((ErrorKind::SysError, "Unable to get the owner of the path"))
+            })?;
+            ids.uid = Some(metadata.uid());
+            ids.gid = Some(metadata.gid());
+        }
+
         if let Some(ref user) = self.user {
-            ids = PrivDrop::lookup_user(user, self.fallback_to_ids_if_names_are_numeric)?;
+            ids = PrivDrop::lookup_user(resolver, user, self.numeric_names())?;
         }
 
         if let Some(ref group) = self.group {
             ids.gid = Some(PrivDrop::lookup_group(
+                resolver,
                 group,
-                self.fallback_to_ids_if_names_are_numeric,
+                self.numeric_names(),
             )?);
         }
 
+        #[cfg(feature = "groups")]
         if let Some(ref group_list) = self.group_list {
-            let mut groups = Vec::with_capacity(group_list.len());
-            for group in group_list {
-                groups.push(PrivDrop::lookup_group(
-                    group,
-                    self.fallback_to_ids_if_names_are_numeric,
-                )?);
-            }
-            ids.group_list = Some(groups);
+            ids.group_list = Some(PrivDrop::lookup_groups(
+                resolver,
+                group_list,
+                self.numeric_names(),
+            )?);
+        }
+
+        #[cfg(feature = "groups")]
+        if let Some(ref group_id_list) = self.group_id_list {
+            ids.group_list
+                .get_or_insert_with(Vec::new)
+                .extend(group_id_list.iter().cloned());
         }
 
         Ok(ids)
     }
+}
 
-    fn do_idchange(&self, ids: UserIds) -> Result<(), PrivDropError> {
-        Self::uidcheck()?;
+/// Parse a name as a numeric identifier
+fn parse_id(name: &OsStr) -> Option<u32> {
+    name.to_str()?.parse().ok()
+}
 
-        let mut groups = vec![];
-        if self.include_default_supplementary_groups {
-            if let (Some(user), Some(gid)) = (&self.user, ids.gid) {
-                if let Some(group_list) = Self::default_group_list(user, gid)? {
-                    groups.extend(group_list);
-                }
-            } else {
-                return Err(PrivDropError::from((
-                    ErrorKind::SysError,
-                    "Unable to determine default supplementary groups without a user name and a base gid",
-                )));
-            }
-        }
-        if let Some(ref group_list) = ids.group_list {
-            groups.extend(group_list.iter().cloned());
-        }
-        if let Some(gid) = ids.gid {
-            groups.push(gid);
-            let mut unique_groups = vec![];
-            for group in groups {
-                if !unique_groups.contains(&group) {
-                    unique_groups.push(group);
-                }
-            }
-            if unsafe { libc::setgroups(unique_groups.len() as _, unique_groups.as_ptr()) } != 0 {
-                return Err(PrivDropError::from((
-                    ErrorKind::SysError,
-                    "Unable to revoke supplementary groups",
-                )));
-            }
-            unistd::setgid(unistd::Gid::from_raw(gid))?;
-        }
-        if let Some(uid) = ids.uid {
-            unistd::setuid(unistd::Uid::from_raw(uid))?
+/// Return `true` if `name` is a number that should be used as an ID instead of the account
+/// with that name, whose ID is `id`
+fn prefer_numeric_id(
+    name: &OsStr,
+    id: u32,
+    numeric_names: Option<NumericNamePolicy>,
+) -> Result<bool, PrivDropError> {
+    match (numeric_names, parse_id(name)) {
+        (Some(NumericNamePolicy::PreferId), Some(numeric_id)) => Ok(numeric_id != id),
+        (Some(NumericNamePolicy::Error), Some(numeric_id)) if numeric_id != id => {
+            Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Ambiguous name: it is both a numeric ID and the name of an account with another ID",
+            )))
         }
-        Ok(())
+        _ => Ok(false),
+    }
+}
+
+fn validate_name(name: &OsStr, error: &'static str) -> Result<(), PrivDropError> {
+    let name = name.as_bytes();
+    if name.is_empty() || name.contains(&0) {
+        return Err(PrivDropError::from((ErrorKind::InvalidConfig, error)));
     }
+    Ok(())
+}
+
+/// Switch to the given user, and to its primary group
+///
+/// This is a shortcut for `PrivDrop::default().user(user).apply()`.
+pub fn drop_to<S: AsRef<OsStr>>(user: S) -> Result<(), PrivDropError> {
+    PrivDrop::default().user(user).apply()
+}
+
+/// chroot() to a directory, then switch to the given user, and to its primary group
+///
+/// This is a shortcut for `PrivDrop::default().chroot(path).user(user).apply()`.
+#[cfg(feature = "chroot")]
+pub fn chroot_and_drop<T: AsRef<Path>, S: AsRef<OsStr>>(
+    path: T,
+    user: S,
+) -> Result<(), PrivDropError> {
+    PrivDrop::default().chroot(path).user(user).apply()
 }
@@ -0,0 +1,56 @@
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::Instant;
+
+use nix::errno::Errno;
+
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+
+/// Raw socket created by `PrivDrop::raw_socket()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawSocket {
+    /// ICMP over IPv4 (`AF_INET`, `IPPROTO_ICMP`), e.g. for ping and traceroute
+    Icmp,
+    /// ICMPv6 (`AF_INET6`, `IPPROTO_ICMPV6`)
+    Icmpv6,
+    /// Another protocol, given as an address family and a protocol number
+    Other(libc::c_int, libc::c_int),
+}
+
+impl RawSocket {
+    fn open(self) -> Result<OwnedFd, PrivDropError> {
+        let (domain, protocol) = match self {
+            RawSocket::Icmp => (libc::AF_INET, libc::IPPROTO_ICMP),
+            RawSocket::Icmpv6 => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+            RawSocket::Other(domain, protocol) => (domain, protocol),
+        };
+        let fd = Errno::result(unsafe { libc::socket(domain, libc::SOCK_RAW, protocol) })?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Errno::result(unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+        Ok(fd)
+    }
+}
+
+impl PrivDrop {
+    /// Apply the changes, and return the sockets declared with `raw_socket()` along with the
+    /// report
+    ///
+    /// The sockets are created with the initial privileges, right before changing the root
+    /// directory, and are returned in the order they were declared.
+    pub fn apply_with_raw_sockets(
+        &self,
+    ) -> Result<(AppliedPrivileges, Vec<OwnedFd>), PrivDropError> {
+        let res = Self::preload().and_then(|_| {
+            let start = Instant::now();
+            let resolved = self.resolve()?;
+            self.apply_resolved(resolved, start.elapsed(), || {
+                self.raw_sockets
+                    .iter()
+                    .map(|socket| socket.open())
+                    .collect()
+            })
+        });
+        res.map_err(|e| self.explain(e))
+    }
+}
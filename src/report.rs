@@ -0,0 +1,252 @@
+#[cfg(feature = "chroot")]
+use std::ffi::OsStr;
+use std::fmt;
+#[cfg(feature = "chroot")]
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nix::unistd;
+
+use super::ephemeral::EphemeralLock;
+use super::errors::*;
+use super::privdrop::*;
+use super::resolved::*;
+
+#[test]
+fn test_diff() {
+    let before = AppliedPrivileges {
+        uid: 0,
+        gid: 0,
+        groups: vec![0, 10, 29],
+        truncated_groups: vec![],
+        chroot: None,
+        cwd: None,
+        timings: PhaseTimings::default(),
+        ephemeral_lock: None,
+    };
+    let after = AppliedPrivileges {
+        uid: 65534,
+        groups: vec![0],
+        chroot: Some(PathBuf::from("/var/empty")),
+        ..before.clone()
+    };
+    let diff = before.diff(&after);
+    assert_eq!(diff.uid, Some((0, 65534)));
+    assert_eq!(diff.gid, None);
+    assert_eq!(diff.dropped_groups, vec![10, 29]);
+    assert!(diff.added_groups.is_empty());
+    assert_eq!(
+        diff.to_string(),
+        "uid 0\u{2192}65534; dropped groups: 10, 29; chroot gained: /var/empty"
+    );
+    assert!(before.diff(&before).is_empty());
+}
+
+/// Credentials of the process after the changes have been applied
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AppliedPrivileges {
+    /// Real user ID
+    pub uid: libc::uid_t,
+    /// Real group ID
+    pub gid: libc::gid_t,
+    /// Supplementary groups
+    pub groups: Vec<libc::gid_t>,
+    /// Supplementary groups left out to stay within the system limit, with
+    /// `GroupLimitPolicy::Truncate` or `GroupLimitPolicy::PreferExplicit`
+    pub truncated_groups: Vec<libc::gid_t>,
+    /// Root directory the process has been confined to, if any
+    pub chroot: Option<PathBuf>,
+    /// Current working directory, if it could be determined
+    pub cwd: Option<PathBuf>,
+    /// Time spent in each phase
+    pub timings: PhaseTimings,
+    /// Lock on the ID allocated with `ephemeral_ids()` and `ephemeral_lock_dir()`, released
+    /// once every copy of the report has been dropped
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub ephemeral_lock: Option<EphemeralLock>,
+}
+
+/// Time spent in each phase of applying the changes
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseTimings {
+    /// Name lookups and computation of the final credentials
+    pub resolution: Duration,
+    /// Change of the root directory
+    pub chroot: Duration,
+    /// Change of the supplementary groups and of the group ID
+    pub group_change: Duration,
+    /// Change of the user ID
+    pub uid_change: Duration,
+}
+
+/// Changes that had already been made when applying the others failed
+///
+/// None of them can be undone: a process in this state should usually exit.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartialApplication {
+    /// The process was moved to new namespaces
+    pub namespaces: bool,
+    /// The root directory was changed
+    pub chroot: bool,
+    /// The supplementary groups were changed
+    pub groups: bool,
+    /// The group ID was changed
+    pub gid: bool,
+    /// The user ID was changed
+    pub uid: bool,
+}
+
+impl PartialApplication {
+    /// Return `true` if no changes were made
+    pub fn is_empty(&self) -> bool {
+        *self == PartialApplication::default()
+    }
+}
+
+impl AppliedPrivileges {
+    pub(crate) fn current(
+        resolved: &ResolvedPrivDrop,
+        timings: PhaseTimings,
+    ) -> Result<Self, PrivDropError> {
+        #[cfg(feature = "chroot")]
+        let chroot = resolved
+            .chroot
+            .as_ref()
+            .map(|x| PathBuf::from(OsStr::from_bytes(x.as_bytes())));
+        #[cfg(not(feature = "chroot"))]
+        let chroot = None;
+        Ok(AppliedPrivileges {
+            uid: unistd::getuid().as_raw(),
+            gid: unistd::getgid().as_raw(),
+            groups: ResolvedPrivDrop::current_groups()?,
+            truncated_groups: resolved.truncated_groups.clone(),
+            chroot,
+            cwd: std::env::current_dir().ok(),
+            timings,
+            ephemeral_lock: None,
+        })
+    }
+
+    /// Compute the changes between these credentials and `after`
+    pub fn diff(&self, after: &AppliedPrivileges) -> CredentialDiff {
+        CredentialDiff {
+            uid: changed(self.uid, after.uid),
+            gid: changed(self.gid, after.gid),
+            dropped_groups: self
+                .groups
+                .iter()
+                .filter(|group| !after.groups.contains(group))
+                .cloned()
+                .collect(),
+            added_groups: after
+                .groups
+                .iter()
+                .filter(|group| !self.groups.contains(group))
+                .cloned()
+                .collect(),
+            chroot: changed(self.chroot.clone(), after.chroot.clone()),
+        }
+    }
+}
+
+impl PrivDrop {
+    /// Capture the current credentials of the process, e.g. to log them before applying changes
+    ///
+    /// A process can't tell the path of its own root directory, so `chroot` is always `None`.
+    /// Timings are left at zero.
+    pub fn current() -> Result<AppliedPrivileges, PrivDropError> {
+        Ok(AppliedPrivileges {
+            uid: unistd::getuid().as_raw(),
+            gid: unistd::getgid().as_raw(),
+            groups: ResolvedPrivDrop::current_groups()?,
+            truncated_groups: vec![],
+            chroot: None,
+            cwd: std::env::current_dir().ok(),
+            timings: PhaseTimings::default(),
+            ephemeral_lock: None,
+        })
+    }
+}
+
+/// Differences between two sets of credentials, computed by `AppliedPrivileges::diff()`
+///
+/// The `Display` implementation produces a short summary suitable for logs.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CredentialDiff {
+    /// Previous and new user ID, if it changed
+    pub uid: Option<(libc::uid_t, libc::uid_t)>,
+    /// Previous and new group ID, if it changed
+    pub gid: Option<(libc::gid_t, libc::gid_t)>,
+    /// Supplementary groups that are not present any more
+    pub dropped_groups: Vec<libc::gid_t>,
+    /// Supplementary groups that have been added
+    pub added_groups: Vec<libc::gid_t>,
+    /// Previous and new root directory, if it changed
+    pub chroot: Option<(Option<PathBuf>, Option<PathBuf>)>,
+}
+
+impl CredentialDiff {
+    /// Return `true` if the credentials are identical
+    pub fn is_empty(&self) -> bool {
+        *self == CredentialDiff::default()
+    }
+}
+
+fn changed<T: PartialEq>(before: T, after: T) -> Option<(T, T)> {
+    if before != after {
+        Some((before, after))
+    } else {
+        None
+    }
+}
+
+fn join_ids(ids: &[libc::gid_t]) -> String {
+    ids.iter()
+        .map(|id| id.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl fmt::Display for CredentialDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        let mut changes = vec![];
+        if let Some((before, after)) = self.uid {
+            changes.push(format!("uid {}\u{2192}{}", before, after));
+        }
+        if let Some((before, after)) = self.gid {
+            changes.push(format!("gid {}\u{2192}{}", before, after));
+        }
+        if !self.dropped_groups.is_empty() {
+            changes.push(format!(
+                "dropped groups: {}",
+                join_ids(&self.dropped_groups)
+            ));
+        }
+        if !self.added_groups.is_empty() {
+            changes.push(format!("added groups: {}", join_ids(&self.added_groups)));
+        }
+        match self.chroot {
+            Some((None, Some(ref after))) => {
+                changes.push(format!("chroot gained: {}", after.display()))
+            }
+            Some((Some(ref before), None)) => {
+                changes.push(format!("chroot lost: {}", before.display()))
+            }
+            Some((Some(ref before), Some(ref after))) => changes.push(format!(
+                "chroot {}\u{2192}{}",
+                before.display(),
+                after.display()
+            )),
+            _ => {}
+        }
+        if changes.is_empty() {
+            return "no changes".fmt(f);
+        }
+        changes.join("; ").fmt(f)
+    }
+}
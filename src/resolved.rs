@@ -0,0 +1,962 @@
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+use std::convert::TryInto;
+use std::ffi::CString;
+#[cfg(feature = "chroot")]
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+#[cfg(feature = "chroot")]
+use std::os::unix::io::{AsRawFd, OwnedFd};
+#[cfg(feature = "chroot")]
+use std::path::Path;
+use std::time::Instant;
+
+use nix::errno::Errno;
+use nix::unistd;
+
+#[cfg(all(feature = "caps", target_os = "linux"))]
+use super::capabilities;
+#[cfg(feature = "chroot")]
+use super::chroot;
+use super::errors::*;
+#[cfg(all(feature = "chroot", target_os = "linux"))]
+use super::fdpass;
+use super::hardening;
+use super::identity;
+use super::privdrop::*;
+use super::report::*;
+use super::resources;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+use super::seccomp;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use super::userns;
+
+#[test]
+fn test_serialize() {
+    let resolved = ResolvedPrivDrop::deserialize("uid=65534;gid=65534;groups=65534,100").unwrap();
+    assert_eq!(resolved.uid, Some(65534));
+    assert_eq!(resolved.gid, Some(65534));
+    assert_eq!(resolved.groups, vec![65534, 100]);
+    assert_eq!(
+        ResolvedPrivDrop::deserialize(&resolved.serialize()).unwrap(),
+        resolved
+    );
+    assert!(ResolvedPrivDrop::deserialize("uid=nobody").is_err());
+    assert!(ResolvedPrivDrop::deserialize("chroot_fd=1").is_err());
+}
+
+#[cfg(feature = "chroot")]
+#[test]
+fn test_chroot_fd_not_serialized() {
+    let fd = OwnedFd::from(std::fs::File::open("/").unwrap());
+    let resolved = ResolvedPrivDrop::deserialize("uid=65534")
+        .unwrap()
+        .chroot_fd(fd);
+    assert!(!resolved.serialize().contains("fd"));
+}
+
+#[test]
+fn test_check_fork_safe() {
+    let resolved = ResolvedPrivDrop::deserialize("uid=65534;gid=65534;groups=65534").unwrap();
+    assert!(resolved.check_fork_safe().is_ok());
+    #[cfg(feature = "groups")]
+    {
+        let resolved = ResolvedPrivDrop::deserialize("gid=65534;initgroups=6e6f626f6479").unwrap();
+        let e = resolved.check_fork_safe().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+    }
+    #[cfg(feature = "chroot")]
+    {
+        let resolved = ResolvedPrivDrop::deserialize("chroot=2f;chroot_overlay=1").unwrap();
+        let e = resolved.check_fork_safe().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+    }
+}
+
+#[test]
+fn test_setgroups() {
+    let groups = ResolvedPrivDrop::current_groups().unwrap();
+    let res = ResolvedPrivDrop::setgroups(&groups);
+    assert_eq!(res.is_ok(), unistd::geteuid().is_root());
+    assert_eq!(ResolvedPrivDrop::current_groups().unwrap(), groups);
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn test_deny_setuid_family() {
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        let denied = |ret: libc::c_int| ret == -1 && Errno::last() == Errno::EPERM;
+        let ok = ResolvedPrivDrop::default().do_deny_setuid_family().is_ok()
+            && unsafe {
+                denied(libc::setuid(libc::getuid()))
+                    && denied(libc::setgid(libc::getgid()))
+                    && denied(libc::setresuid(u32::MAX, u32::MAX, u32::MAX))
+                    && denied(libc::setgroups(0, std::ptr::null()))
+                    && libc::getpid() > 0
+            };
+        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+#[test]
+fn test_progress_after_failed_setgid() {
+    if !unistd::geteuid().is_root() {
+        return;
+    }
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        // Only the group ID change fails, once the supplementary groups have been replaced.
+        let mut filter = seccomp::filter_for_syscalls(
+            &[libc::SYS_setgid, libc::SYS_setregid, libc::SYS_setresgid],
+            libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+        );
+        let resolved = ResolvedPrivDrop::deserialize("gid=65534;groups=65534").unwrap();
+        let mut progress = PartialApplication::default();
+        let ok = seccomp::install_filter(&mut filter, 0).is_ok()
+            && resolved.do_groupchange(&mut progress).is_err()
+            && progress.groups
+            && !progress.gid;
+        unsafe { libc::_exit(if ok { 0 } else { 1 }) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0);
+}
+
+#[test]
+fn test_fingerprint() {
+    let a = ResolvedPrivDrop::deserialize("uid=65534;gid=65534;groups=65534,100").unwrap();
+    let b = ResolvedPrivDrop::deserialize("uid=65534;gid=65534;groups=100,65534").unwrap();
+    let c = ResolvedPrivDrop::deserialize("uid=65533;gid=65534;groups=100,65534").unwrap();
+    assert_eq!(a.fingerprint(), b.fingerprint());
+    assert_ne!(a.fingerprint(), c.fingerprint());
+    assert_eq!(a.fingerprint(), 0x68b5_fe78_b8ca_2cbe);
+}
+
+/// Credentials resolved by `PrivDrop::resolve()`
+///
+/// Applying a `ResolvedPrivDrop` doesn't perform any name lookups nor memory allocations,
+/// so it can safely be done right after `fork()`, except when it includes:
+///
+/// - `use_initgroups()`, as `initgroups()` queries the name service;
+/// - `chroot_overlay()`, as the overlay directories are created when it is mounted;
+/// - retained capabilities;
+/// - `clear_env()`, on platforms other than Linux, Android and FreeBSD.
+///
+/// `ForkTemplate` and `PrivDrop::apply_in_child()` reject these.
+///
+/// With the `serde` feature, it can be serialized as a plan of the changes that would be
+/// applied. Paths and names are serialized as strings, with invalid UTF-8 replaced.
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ResolvedPrivDrop {
+    #[cfg(feature = "chroot")]
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_cstring"))]
+    pub(crate) chroot: Option<CString>,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_fd: Option<chroot::ChrootFd>,
+    #[cfg(feature = "chroot")]
+    pub(crate) chroot_overlay: bool,
+    #[cfg(feature = "chroot")]
+    pub(crate) groups_before_chroot: bool,
+    pub(crate) mount_propagation: MountPropagation,
+    pub(crate) uid: Option<libc::uid_t>,
+    pub(crate) gid: Option<libc::gid_t>,
+    pub(crate) groups: Vec<libc::gid_t>,
+    pub(crate) truncated_groups: Vec<libc::gid_t>,
+    pub(crate) skip_setgroups: bool,
+    pub(crate) deny_setuid_family: bool,
+    pub(crate) clear_saved_ids: bool,
+    pub(crate) no_new_privs: bool,
+    pub(crate) disable_core_dumps: bool,
+    pub(crate) umask: Option<libc::mode_t>,
+    pub(crate) clear_env: bool,
+    pub(crate) cloexec_fds: bool,
+    pub(crate) lock_securebits: bool,
+    pub(crate) disabled_speculation: Vec<Speculation>,
+    pub(crate) disable_tracing: bool,
+    pub(crate) ptracer: Option<Ptracer>,
+    pub(crate) max_processes: Option<libc::rlim_t>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_cstring"))]
+    pub(crate) chdir: Option<CString>,
+    pub(crate) detach_tty: bool,
+    pub(crate) cpu_affinity: Option<Vec<usize>>,
+    pub(crate) sched_policy: Option<(SchedPolicy, libc::c_int)>,
+    pub(crate) routing_table: Option<libc::c_int>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_bytes"))]
+    pub(crate) hostname: Option<Vec<u8>>,
+    pub(crate) notify_fd: Option<RawFd>,
+    #[cfg(feature = "groups")]
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_cstring"))]
+    pub(crate) initgroups_user: Option<CString>,
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    pub(crate) keep_caps: Vec<caps::Capability>,
+}
+
+impl ResolvedPrivDrop {
+    /// Apply the changes
+    pub fn apply(&self) -> Result<(), PrivDropError> {
+        self.apply_with_timings().map(|_| ())
+    }
+
+    /// Apply the changes, and return the time spent in each phase
+    ///
+    /// The resolution time is not known at this point, and is left at zero.
+    ///
+    /// All the checks that don't require changes are performed first. Then, in this order, the
+    /// process detaches from its terminal, enters a new UTS namespace, changes its groups if
+    /// they come first, changes its root directory, changes its groups otherwise, and finally
+    /// its user. Once the first of these steps has succeeded, a failure leaves the process
+    /// partially confined, and it should exit rather than keep running.
+    pub fn apply_with_timings(&self) -> Result<PhaseTimings, PrivDropError> {
+        self.apply_tracked(&mut PartialApplication::default())
+    }
+
+    /// Like `apply_with_timings()`, recording the irreversible changes in `progress` as they
+    /// are made
+    pub(crate) fn apply_tracked(
+        &self,
+        progress: &mut PartialApplication,
+    ) -> Result<PhaseTimings, PrivDropError> {
+        self.check()?;
+        self.apply_checked(progress)
+    }
+
+    /// Like `apply_tracked()`, once `check()` has succeeded
+    pub(crate) fn apply_checked(
+        &self,
+        progress: &mut PartialApplication,
+    ) -> Result<PhaseTimings, PrivDropError> {
+        let mut timings = PhaseTimings::default();
+        if self.detach_tty {
+            hardening::detach_tty()?;
+        }
+        if let Some(ref hostname) = self.hostname {
+            resources::set_hostname_in_new_namespace(hostname)?;
+            progress.namespaces = true;
+        }
+        #[cfg(feature = "chroot")]
+        let groups_first = self.groups_before_chroot;
+        #[cfg(not(feature = "chroot"))]
+        let groups_first = false;
+        if groups_first {
+            self.change_groups(&mut timings, progress)?;
+        }
+        let start = Instant::now();
+        self.do_chroot()?;
+        timings.chroot = start.elapsed();
+        #[cfg(feature = "chroot")]
+        if self.has_chroot() {
+            progress.namespaces |= self.chroot_overlay;
+            progress.chroot = true;
+        }
+        self.before_drop()?;
+        if !groups_first {
+            self.change_groups(&mut timings, progress)?;
+        }
+        let start = Instant::now();
+        self.do_uidchange()?;
+        timings.uid_change = start.elapsed();
+        progress.uid = self.uid.is_some();
+        self.after_drop()?;
+        Ok(timings)
+    }
+
+    /// Like `apply_tracked()`, confining an unprivileged process in a new user namespace
+    /// instead of changing its credentials
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn apply_sandboxed(
+        &self,
+        ns_uid: libc::uid_t,
+        ns_gid: libc::gid_t,
+        progress: &mut PartialApplication,
+    ) -> Result<PhaseTimings, PrivDropError> {
+        self.check()?;
+        let mut timings = PhaseTimings::default();
+        if self.detach_tty {
+            hardening::detach_tty()?;
+        }
+        let start = Instant::now();
+        userns::enter_user_namespace(ns_uid, ns_gid, self.mount_propagation)?;
+        progress.namespaces = true;
+        if let Some(ref hostname) = self.hostname {
+            resources::set_hostname_in_new_namespace(hostname)?;
+        }
+        #[cfg(feature = "chroot")]
+        {
+            self.enter_chroot()?;
+            progress.chroot = self.has_chroot();
+        }
+        timings.chroot = start.elapsed();
+        self.before_drop()?;
+        userns::drop_all_capabilities()?;
+        self.after_drop()?;
+        Ok(timings)
+    }
+
+    /// Like `apply_tracked()`, confining an unprivileged process in a new user namespace
+    /// instead of changing its credentials
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn apply_sandboxed(
+        &self,
+        _ns_uid: libc::uid_t,
+        _ns_gid: libc::gid_t,
+        _progress: &mut PartialApplication,
+    ) -> Result<PhaseTimings, PrivDropError> {
+        Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unprivileged sandboxes are only supported on Linux",
+        )))
+    }
+
+    /// Steps performed after the root directory has been changed, while the process still
+    /// has its privileges
+    fn before_drop(&self) -> Result<(), PrivDropError> {
+        if let Some(ref chdir) = self.chdir {
+            Errno::result(unsafe { libc::chdir(chdir.as_ptr()) })?;
+        }
+        if self.lock_securebits {
+            hardening::lock_securebits()?;
+        }
+        for speculation in &self.disabled_speculation {
+            hardening::disable_speculation(*speculation)?;
+        }
+        if self.disable_tracing {
+            hardening::disable_tracing()?;
+        }
+        if let Some(ref cpus) = self.cpu_affinity {
+            resources::set_cpu_affinity(cpus)?;
+        }
+        if let Some((policy, priority)) = self.sched_policy {
+            resources::set_sched_policy(policy, priority)?;
+        }
+        if let Some(rtable) = self.routing_table {
+            resources::set_routing_table(rtable)?;
+        }
+        Ok(())
+    }
+
+    /// Steps performed once the privileges have been dropped
+    fn after_drop(&self) -> Result<(), PrivDropError> {
+        if let Some(limit) = self.max_processes {
+            resources::set_process_limit(limit)?;
+        }
+        if let Some(umask) = self.umask {
+            unsafe { libc::umask(umask) };
+        }
+        if self.clear_env {
+            hardening::clear_env()?;
+        }
+        if self.cloexec_fds {
+            hardening::set_cloexec_on_fds()?;
+        }
+        if self.disable_core_dumps {
+            hardening::disable_core_dumps()?;
+        }
+        if let Some(ptracer) = self.ptracer {
+            hardening::set_ptracer(ptracer)?;
+        }
+        if self.no_new_privs {
+            hardening::set_no_new_privs()?;
+        }
+        if self.deny_setuid_family {
+            self.do_deny_setuid_family()?;
+        }
+        if let Some(fd) = self.notify_fd {
+            Self::notify(fd)?;
+        }
+        Ok(())
+    }
+
+    fn change_groups(
+        &self,
+        timings: &mut PhaseTimings,
+        progress: &mut PartialApplication,
+    ) -> Result<(), PrivDropError> {
+        let start = Instant::now();
+        self.do_groupchange(progress)?;
+        timings.group_change = start.elapsed();
+        Ok(())
+    }
+
+    fn notify(fd: RawFd) -> Result<(), PrivDropError> {
+        let mut message: &[u8] = b"\n";
+        let eventfd_increment = 1u64.to_ne_bytes();
+        loop {
+            if unsafe { libc::write(fd, message.as_ptr() as *const _, message.len()) } >= 0 {
+                return Ok(());
+            }
+            let e = nix::Error::last();
+            match e {
+                nix::Error::EINTR => {}
+                // An eventfd only accepts 8-byte counter increments.
+                nix::Error::EINVAL if message.len() == 1 => message = &eventfd_increment,
+                _ => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Check that the changes can be applied, without performing any of them
+    pub(crate) fn check(&self) -> Result<(), PrivDropError> {
+        #[cfg(feature = "chroot")]
+        if let Some(ref chroot) = self.chroot {
+            chroot::check_path(chroot)?;
+        }
+        #[cfg(feature = "chroot")]
+        if let Some(ref fd) = self.chroot_fd {
+            chroot::check_fd(fd.as_raw_fd())?;
+        }
+        let ngroups_max = unsafe { libc::sysconf(libc::_SC_NGROUPS_MAX) };
+        if !self.skip_setgroups && ngroups_max >= 0 && self.groups.len() > ngroups_max as usize {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Too many supplementary groups",
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check that applying the changes doesn't query the name service nor allocate, so that
+    /// it can be done right after `fork()`
+    pub(crate) fn check_fork_safe(&self) -> Result<(), PrivDropError> {
+        let unsafe_after_fork =
+            |description| Err(PrivDropError::from((ErrorKind::InvalidConfig, description)));
+        #[cfg(feature = "groups")]
+        if self.initgroups_user.is_some() {
+            return unsafe_after_fork("use_initgroups() can't be applied right after fork()");
+        }
+        #[cfg(feature = "chroot")]
+        if self.chroot_overlay {
+            return unsafe_after_fork("chroot_overlay() can't be applied right after fork()");
+        }
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        if !self.keep_caps.is_empty() {
+            return unsafe_after_fork("Capabilities can't be retained right after fork()");
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+        if self.clear_env {
+            return unsafe_after_fork("clear_env() can't be applied right after fork()");
+        }
+        Ok(())
+    }
+
+    /// Serialize the resolved credentials, e.g. to pass them to a re-executed child
+    ///
+    /// The child can reconstruct them with `deserialize()` without any name lookups. A chroot
+    /// descriptor is not included: it has to be passed separately, e.g. with `recv_chroot_fd()`.
+    pub fn serialize(&self) -> String {
+        let mut fields = vec![];
+        #[cfg(feature = "chroot")]
+        if let Some(ref chroot) = self.chroot {
+            fields.push(format!("chroot={}", hex_encode(chroot.as_bytes())));
+        }
+        #[cfg(feature = "chroot")]
+        if self.chroot_overlay {
+            fields.push("chroot_overlay=1".to_string());
+        }
+        #[cfg(feature = "chroot")]
+        if self.groups_before_chroot {
+            fields.push("groups_before_chroot=1".to_string());
+        }
+        if self.mount_propagation == MountPropagation::Slave {
+            fields.push("mount_propagation=slave".to_string());
+        }
+        if let Some(uid) = self.uid {
+            fields.push(format!("uid={}", uid));
+        }
+        if let Some(gid) = self.gid {
+            fields.push(format!("gid={}", gid));
+        }
+        let groups: Vec<_> = self.groups.iter().map(|group| group.to_string()).collect();
+        fields.push(format!("groups={}", groups.join(",")));
+        if !self.truncated_groups.is_empty() {
+            let truncated_groups: Vec<_> = self
+                .truncated_groups
+                .iter()
+                .map(|group| group.to_string())
+                .collect();
+            fields.push(format!("truncated_groups={}", truncated_groups.join(",")));
+        }
+        if self.skip_setgroups {
+            fields.push("skip_setgroups=1".to_string());
+        }
+        let flags = [
+            ("deny_setuid_family", self.deny_setuid_family),
+            ("clear_saved_ids", self.clear_saved_ids),
+            ("no_new_privs", self.no_new_privs),
+            ("disable_core_dumps", self.disable_core_dumps),
+            ("clear_env", self.clear_env),
+            ("cloexec_fds", self.cloexec_fds),
+            ("lock_securebits", self.lock_securebits),
+            ("disable_tracing", self.disable_tracing),
+            ("detach_tty", self.detach_tty),
+        ];
+        for (name, value) in flags.iter() {
+            if *value {
+                fields.push(format!("{}=1", name));
+            }
+        }
+        if !self.disabled_speculation.is_empty() {
+            let speculation: Vec<_> = self
+                .disabled_speculation
+                .iter()
+                .map(|speculation| match speculation {
+                    Speculation::StoreBypass => "store_bypass",
+                    Speculation::IndirectBranch => "indirect_branch",
+                })
+                .collect();
+            fields.push(format!("disable_speculation={}", speculation.join(",")));
+        }
+        if let Some(ptracer) = self.ptracer {
+            let ptracer = match ptracer {
+                Ptracer::Restricted => "restricted".to_string(),
+                Ptracer::Process(pid) => pid.to_string(),
+                Ptracer::Any => "any".to_string(),
+            };
+            fields.push(format!("ptracer={}", ptracer));
+        }
+        if let Some(ref chdir) = self.chdir {
+            fields.push(format!("chdir={}", hex_encode(chdir.as_bytes())));
+        }
+        if let Some(ref cpus) = self.cpu_affinity {
+            let cpus: Vec<_> = cpus.iter().map(|cpu| cpu.to_string()).collect();
+            fields.push(format!("cpus={}", cpus.join(",")));
+        }
+        if let Some((policy, priority)) = self.sched_policy {
+            let policy = match policy {
+                SchedPolicy::Fifo => "fifo",
+                SchedPolicy::RoundRobin => "rr",
+            };
+            fields.push(format!("sched={},{}", policy, priority));
+        }
+        if let Some(ref hostname) = self.hostname {
+            fields.push(format!("hostname={}", hex_encode(hostname)));
+        }
+        if let Some(fd) = self.notify_fd {
+            fields.push(format!("notify_fd={}", fd));
+        }
+        if let Some(rtable) = self.routing_table {
+            fields.push(format!("rtable={}", rtable));
+        }
+        if let Some(limit) = self.max_processes {
+            fields.push(format!("max_processes={}", limit));
+        }
+        if let Some(umask) = self.umask {
+            fields.push(format!("umask={:o}", umask));
+        }
+        #[cfg(feature = "groups")]
+        if let Some(ref user) = self.initgroups_user {
+            fields.push(format!("initgroups={}", hex_encode(user.as_bytes())));
+        }
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        if !self.keep_caps.is_empty() {
+            let caps: Vec<_> = self.keep_caps.iter().map(|cap| cap.to_string()).collect();
+            fields.push(format!("caps={}", caps.join(",")));
+        }
+        fields.join(";")
+    }
+
+    /// Reconstruct credentials serialized with `serialize()`
+    pub fn deserialize(serialized: &str) -> Result<Self, PrivDropError> {
+        let invalid =
+            || PrivDropError::from((ErrorKind::SysError, "Invalid serialized credentials"));
+        let mut resolved = ResolvedPrivDrop::default();
+        for field in serialized.split(';') {
+            let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+            match key {
+                #[cfg(feature = "chroot")]
+                "chroot" => {
+                    let chroot = hex_decode(value).ok_or_else(invalid)?;
+                    resolved.chroot = Some(CString::new(chroot).map_err(|_| invalid())?);
+                }
+                #[cfg(feature = "chroot")]
+                "chroot_overlay" => resolved.chroot_overlay = value == "1",
+                #[cfg(feature = "chroot")]
+                "groups_before_chroot" => resolved.groups_before_chroot = value == "1",
+                "mount_propagation" => {
+                    resolved.mount_propagation = match value {
+                        "private" => MountPropagation::Private,
+                        "slave" => MountPropagation::Slave,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "uid" => resolved.uid = Some(value.parse().map_err(|_| invalid())?),
+                "gid" => resolved.gid = Some(value.parse().map_err(|_| invalid())?),
+                "groups" if !value.is_empty() => {
+                    for group in value.split(',') {
+                        resolved.groups.push(group.parse().map_err(|_| invalid())?);
+                    }
+                }
+                "groups" => {}
+                "truncated_groups" => {
+                    for group in value.split(',') {
+                        resolved
+                            .truncated_groups
+                            .push(group.parse().map_err(|_| invalid())?);
+                    }
+                }
+                "sched" => {
+                    let (policy, priority) = value.split_once(',').ok_or_else(invalid)?;
+                    let policy = match policy {
+                        "fifo" => SchedPolicy::Fifo,
+                        "rr" => SchedPolicy::RoundRobin,
+                        _ => return Err(invalid()),
+                    };
+                    let priority = priority.parse().map_err(|_| invalid())?;
+                    resolved.sched_policy = Some((policy, priority));
+                }
+                "hostname" => resolved.hostname = Some(hex_decode(value).ok_or_else(invalid)?),
+                "notify_fd" => resolved.notify_fd = Some(value.parse().map_err(|_| invalid())?),
+                "rtable" => resolved.routing_table = Some(value.parse().map_err(|_| invalid())?),
+                "cpus" => {
+                    let mut cpus = vec![];
+                    for cpu in value.split(',').filter(|x| !x.is_empty()) {
+                        cpus.push(cpu.parse().map_err(|_| invalid())?);
+                    }
+                    resolved.cpu_affinity = Some(cpus);
+                }
+                "skip_setgroups" => resolved.skip_setgroups = value == "1",
+                "deny_setuid_family" => resolved.deny_setuid_family = value == "1",
+                "clear_saved_ids" => resolved.clear_saved_ids = value == "1",
+                "no_new_privs" => resolved.no_new_privs = value == "1",
+                "disable_core_dumps" => resolved.disable_core_dumps = value == "1",
+                "clear_env" => resolved.clear_env = value == "1",
+                "cloexec_fds" => resolved.cloexec_fds = value == "1",
+                "lock_securebits" => resolved.lock_securebits = value == "1",
+                "disable_tracing" => resolved.disable_tracing = value == "1",
+                "max_processes" => {
+                    resolved.max_processes = Some(value.parse().map_err(|_| invalid())?)
+                }
+                "ptracer" => {
+                    resolved.ptracer = Some(match value {
+                        "restricted" => Ptracer::Restricted,
+                        "any" => Ptracer::Any,
+                        pid => Ptracer::Process(pid.parse().map_err(|_| invalid())?),
+                    })
+                }
+                "disable_speculation" => {
+                    for speculation in value.split(',') {
+                        resolved.disabled_speculation.push(match speculation {
+                            "store_bypass" => Speculation::StoreBypass,
+                            "indirect_branch" => Speculation::IndirectBranch,
+                            _ => return Err(invalid()),
+                        });
+                    }
+                }
+                "detach_tty" => resolved.detach_tty = value == "1",
+                "chdir" => {
+                    let chdir = hex_decode(value).ok_or_else(invalid)?;
+                    resolved.chdir = Some(CString::new(chdir).map_err(|_| invalid())?);
+                }
+                "umask" => {
+                    let umask = libc::mode_t::from_str_radix(value, 8).map_err(|_| invalid())?;
+                    resolved.umask = Some(umask);
+                }
+                #[cfg(feature = "groups")]
+                "initgroups" => {
+                    let user = hex_decode(value).ok_or_else(invalid)?;
+                    resolved.initgroups_user = Some(CString::new(user).map_err(|_| invalid())?);
+                }
+                #[cfg(all(feature = "caps", target_os = "linux"))]
+                "caps" => {
+                    for cap in value.split(',') {
+                        resolved.keep_caps.push(cap.parse().map_err(|_| invalid())?);
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// chroot() to an open directory, e.g. one passed along with serialized credentials
+    ///
+    /// The descriptor is owned by the credentials, and closed when they are dropped.
+    #[cfg(feature = "chroot")]
+    pub fn chroot_fd(mut self, fd: OwnedFd) -> Self {
+        self.chroot_fd = Some(chroot::ChrootFd::new(fd));
+        self
+    }
+
+    /// Like `chroot_fd()`, with a directory descriptor received over a Unix socket
+    /// (`SCM_RIGHTS`)
+    #[cfg(all(feature = "chroot", target_os = "linux"))]
+    pub fn recv_chroot_fd<S: AsRawFd>(self, socket: &S) -> Result<Self, PrivDropError> {
+        Ok(self.chroot_fd(fdpass::recv_fd(socket.as_raw_fd())?))
+    }
+
+    /// Reconstruct credentials serialized with `serialize()` and stored in an environment variable
+    pub fn from_env<K: AsRef<std::ffi::OsStr>>(key: K) -> Result<Self, PrivDropError> {
+        let serialized = std::env::var(key).map_err(|_| {
+            PrivDropError::from((
+                ErrorKind::SysError,
+                "Serialized credentials not found in the environment",
+            ))
+        })?;
+        Self::deserialize(&serialized)
+    }
+
+    /// Compute a stable fingerprint of the target credentials, e.g. to let logs and alerting
+    /// rules detect when a service's drop target changes between releases
+    ///
+    /// Only the user and group IDs, the sorted supplementary groups and the canonical chroot
+    /// path are taken into account. The hash function (64-bit FNV-1a) will not change.
+    pub fn fingerprint(&self) -> u64 {
+        let mut groups = self.groups.clone();
+        groups.sort_unstable();
+        groups.dedup();
+        let id = |id: Option<u32>| id.map(|id| id.to_string()).unwrap_or_default();
+        let groups: Vec<_> = groups.iter().map(|group| group.to_string()).collect();
+        #[cfg(feature = "chroot")]
+        let chroot = self.chroot.as_ref().map(|chroot| {
+            let path = Path::new(OsStr::from_bytes(chroot.as_bytes()));
+            std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+        });
+        #[cfg(not(feature = "chroot"))]
+        let chroot: Option<std::path::PathBuf> = None;
+        let mut normalized = format!(
+            "uid={};gid={};groups={}",
+            id(self.uid),
+            id(self.gid),
+            groups.join(",")
+        )
+        .into_bytes();
+        if let Some(chroot) = chroot {
+            normalized.extend_from_slice(b";chroot=");
+            normalized.extend_from_slice(chroot.as_os_str().as_bytes());
+        }
+        normalized.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &c| {
+            (hash ^ c as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn do_deny_setuid_family(&self) -> Result<(), PrivDropError> {
+        const SYSCALLS: [libc::c_long; 9] = [
+            libc::SYS_setuid,
+            libc::SYS_setgid,
+            libc::SYS_setgroups,
+            libc::SYS_setreuid,
+            libc::SYS_setregid,
+            libc::SYS_setresuid,
+            libc::SYS_setresgid,
+            libc::SYS_setfsuid,
+            libc::SYS_setfsgid,
+        ];
+        // The filter is built on the stack, as this can run right after fork().
+        let mut filter = [seccomp::EMPTY_INSN; seccomp::filter_len(SYSCALLS.len())];
+        seccomp::fill_filter(
+            &SYSCALLS,
+            libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            &mut filter,
+        );
+        seccomp::install_filter(&mut filter, 0)?;
+        Ok(())
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    fn do_deny_setuid_family(&self) -> Result<(), PrivDropError> {
+        Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Denying credential changes is not supported on this platform",
+        )))
+    }
+
+    fn uidcheck() -> Result<(), PrivDropError> {
+        if !unistd::geteuid().is_root() {
+            Err(PrivDropError::from((
+                ErrorKind::PermissionDenied,
+                "Starting this application requires root privileges",
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "chroot")]
+    fn do_chroot(&self) -> Result<(), PrivDropError> {
+        if self.has_chroot() {
+            Self::uidcheck()?;
+            self.enter_chroot()?;
+        }
+        Ok(())
+    }
+
+    /// Path of the chroot directory, if it was not given as a descriptor
+    #[cfg(feature = "chroot")]
+    pub(crate) fn chroot_path(&self) -> Option<&Path> {
+        self.chroot
+            .as_ref()
+            .map(|chroot| Path::new(OsStr::from_bytes(chroot.as_bytes())))
+    }
+
+    /// Return `true` if the root directory is going to be changed
+    #[cfg(feature = "chroot")]
+    pub(crate) fn has_chroot(&self) -> bool {
+        self.chroot.is_some() || self.chroot_fd.is_some()
+    }
+
+    /// Change the root directory, without any checks
+    #[cfg(feature = "chroot")]
+    pub(crate) fn enter_chroot(&self) -> Result<(), PrivDropError> {
+        if let Some(ref chroot) = self.chroot {
+            chroot::enter_path(chroot, self.chroot_overlay, self.mount_propagation)?;
+        } else if let Some(ref fd) = self.chroot_fd {
+            chroot::enter_fd(fd.as_raw_fd())?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "chroot"))]
+    fn do_chroot(&self) -> Result<(), PrivDropError> {
+        Ok(())
+    }
+
+    fn do_groupchange(&self, progress: &mut PartialApplication) -> Result<(), PrivDropError> {
+        Self::uidcheck()?;
+
+        if let Some(gid) = self.gid {
+            if !self.skip_setgroups {
+                self.do_setgroups(gid, progress)?;
+            }
+            identity::set_gid(gid, self.clear_saved_ids)?;
+            progress.gid = true;
+        }
+        Ok(())
+    }
+
+    fn do_uidchange(&self) -> Result<(), PrivDropError> {
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        capabilities::keep_through_setuid(&self.keep_caps)?;
+        if let Some(uid) = self.uid {
+            identity::set_uid(uid, self.clear_saved_ids)?;
+        }
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        capabilities::restrict_to(&self.keep_caps)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "groups")]
+    fn do_setgroups(
+        &self,
+        gid: libc::gid_t,
+        progress: &mut PartialApplication,
+    ) -> Result<(), PrivDropError> {
+        let user = match self.initgroups_user {
+            None => {
+                Self::setgroups(&self.groups)?;
+                progress.groups = true;
+                return Ok(());
+            }
+            Some(ref user) => user,
+        };
+        if unsafe { libc::initgroups(user.as_ptr(), gid as _) } != 0 {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Unable to initialize supplementary groups",
+            )));
+        }
+        progress.groups = true;
+        if self.groups.iter().any(|&group| group != gid) {
+            let mut groups = Self::current_groups()?;
+            for &group in &self.groups {
+                if !groups.contains(&group) {
+                    groups.push(group);
+                }
+            }
+            Self::setgroups(&groups)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "groups"))]
+    fn do_setgroups(
+        &self,
+        _gid: libc::gid_t,
+        progress: &mut PartialApplication,
+    ) -> Result<(), PrivDropError> {
+        Self::setgroups(&self.groups)?;
+        progress.groups = true;
+        Ok(())
+    }
+
+    pub(crate) fn current_groups() -> Result<Vec<libc::gid_t>, PrivDropError> {
+        let ngroups = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+        if ngroups < 0 {
+            return Err(nix::Error::last().into());
+        }
+        let mut groups: Vec<libc::gid_t> = vec![0; ngroups as usize];
+        let ngroups = unsafe { libc::getgroups(ngroups, groups.as_mut_ptr()) };
+        if ngroups < 0 {
+            return Err(nix::Error::last().into());
+        }
+        groups.truncate(ngroups as usize);
+        Ok(groups)
+    }
+
+    /// Replace the supplementary groups, without allocating
+    pub(crate) fn setgroups(groups: &[libc::gid_t]) -> Result<(), PrivDropError> {
+        // The count is a `size_t` on Linux, and an `int` on other platforms.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let ngroups = groups.len();
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let ngroups: libc::c_int = groups.len().try_into().map_err(|_| {
+            PrivDropError::from((ErrorKind::InvalidConfig, "Too many supplementary groups"))
+        })?;
+        Errno::result(unsafe { libc::setgroups(ngroups, groups.as_ptr()) })?;
+        Ok(())
+    }
+}
+
+fn hex_encode(bin: &[u8]) -> String {
+    bin.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+fn hex_decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() & 1 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(feature = "serde")]
+fn serialize_bytes<S: serde::Serializer>(
+    value: &Option<Vec<u8>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serializer.serialize_some(&String::from_utf8_lossy(value)),
+        None => serializer.serialize_none(),
+    }
+}
+
+#[cfg(feature = "serde")]
+fn serialize_cstring<S: serde::Serializer>(
+    value: &Option<CString>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serialize_bytes(&value.as_ref().map(|x| x.as_bytes().to_vec()), serializer)
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+
+use nix::errno::Errno;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+use super::errors::*;
+
+#[test]
+fn test_debug_omits_buffer() {
+    let mut resolver = Resolver::new();
+    resolver.buf = "secret".bytes().map(|c| c as libc::c_char).collect();
+    let debug = format!("{:?}", resolver);
+    assert!(debug.contains("max_buf_len"));
+    assert!(!debug.contains("buf:"));
+    assert!(!debug.contains(&format!("{:?}", resolver.buf)));
+}
+
+#[test]
+fn test_group_exists() {
+    let mut resolver = Resolver::new();
+    assert!(resolver.group_exists(0).unwrap());
+    assert!(!resolver.group_exists(4_000_000_000).unwrap());
+    assert_eq!(resolver.gids.len(), 2);
+}
+
+const INITIAL_BUF_LEN: usize = 4096;
+const DEFAULT_MAX_BUF_LEN: usize = 1 << 20;
+const DEFAULT_MAX_RETRIES: usize = 16;
+
+/// Name service lookups, with a reusable buffer and a cache of the records already found
+///
+/// `PrivDrop::resolve()` uses a new resolver every time. Keeping one around and passing it to
+/// `PrivDrop::resolve_with()` avoids allocating buffers and querying the name service again
+/// when resolving several configurations sharing the same users and groups. It also allows
+/// changing the limits on the size of the lookup buffer.
+///
+/// With the `zeroize` feature, the lookup buffer, which may contain password hashes and GECOS
+/// fields, is overwritten as soon as the relevant fields have been copied out of it.
+#[derive(Clone, Default)]
+pub struct Resolver {
+    buf: Vec<libc::c_char>,
+    users: HashMap<OsString, Option<(libc::uid_t, libc::gid_t, OsString)>>,
+    groups: HashMap<OsString, Option<libc::gid_t>>,
+    uids: HashMap<libc::uid_t, Option<(OsString, libc::gid_t)>>,
+    max_buf_len: Option<usize>,
+    max_retries: Option<usize>,
+    gids: HashMap<libc::gid_t, bool>,
+}
+
+/// The lookup buffer is left out, as it may still contain records
+impl fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolver")
+            .field("users", &self.users)
+            .field("groups", &self.groups)
+            .field("uids", &self.uids)
+            .field("gids", &self.gids)
+            .field("max_buf_len", &self.max_buf_len)
+            .field("max_retries", &self.max_retries)
+            .finish()
+    }
+}
+
+impl Resolver {
+    /// Create a resolver with an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum size of the lookup buffer, in bytes (default: 1 MiB)
+    ///
+    /// Lookups requiring a larger buffer fail with an error of kind `ErrorKind::LookupLimit`.
+    pub fn max_buffer_size(mut self, size: usize) -> Self {
+        self.max_buf_len = Some(size);
+        self
+    }
+
+    /// Set how many times a lookup can be retried with a larger buffer (default: 16)
+    ///
+    /// Lookups requiring more retries fail with an error of kind `ErrorKind::LookupLimit`.
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.max_retries = Some(retries);
+        self
+    }
+
+    /// Forget the cached records, e.g. after the user database has changed
+    pub fn clear(&mut self) {
+        self.users.clear();
+        self.groups.clear();
+        self.uids.clear();
+        self.gids.clear();
+    }
+
+    /// Return the user and primary group IDs of `user`, or `None` if the user doesn't exist
+    pub(crate) fn user(
+        &mut self,
+        user: &OsStr,
+    ) -> Result<Option<(libc::uid_t, libc::gid_t)>, PrivDropError> {
+        Ok(self.passwd(user)?.map(|(uid, gid, _)| (uid, gid)))
+    }
+
+    /// Return the home directory of `user`, or `None` if the user doesn't exist
+    pub(crate) fn home_dir(&mut self, user: &OsStr) -> Result<Option<OsString>, PrivDropError> {
+        Ok(self.passwd(user)?.map(|(_, _, home)| home))
+    }
+
+    fn passwd(
+        &mut self,
+        user: &OsStr,
+    ) -> Result<Option<(libc::uid_t, libc::gid_t, OsString)>, PrivDropError> {
+        if let Some(entry) = self.users.get(user) {
+            return Ok(entry.clone());
+        }
+        let name = CString::new(user.as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid username")))?;
+        let mut pwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+        let mut result = std::ptr::null_mut();
+        self.lookup(|buf| unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        })?;
+        let entry = if result.is_null() {
+            None
+        } else {
+            let home = unsafe { CStr::from_ptr(pwd.pw_dir) };
+            Some((
+                pwd.pw_uid,
+                pwd.pw_gid,
+                OsStr::from_bytes(home.to_bytes()).to_os_string(),
+            ))
+        };
+        self.wipe();
+        self.users.insert(user.to_os_string(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Return the name and primary group ID of the account with the ID `uid`, or `None` if
+    /// there is no such account
+    pub(crate) fn user_by_uid(
+        &mut self,
+        uid: libc::uid_t,
+    ) -> Result<Option<(OsString, libc::gid_t)>, PrivDropError> {
+        if let Some(account) = self.uids.get(&uid) {
+            return Ok(account.clone());
+        }
+        let mut pwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+        let mut result = std::ptr::null_mut();
+        self.lookup(|buf| unsafe {
+            libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+        })?;
+        let account = if result.is_null() {
+            None
+        } else {
+            let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+            Some((
+                OsStr::from_bytes(name.to_bytes()).to_os_string(),
+                pwd.pw_gid,
+            ))
+        };
+        self.wipe();
+        self.uids.insert(uid, account.clone());
+        Ok(account)
+    }
+
+    /// Return the ID of `group`, or `None` if the group doesn't exist
+    pub(crate) fn group(&mut self, group: &OsStr) -> Result<Option<libc::gid_t>, PrivDropError> {
+        if let Some(gid) = self.groups.get(group) {
+            return Ok(*gid);
+        }
+        let name = CString::new(group.as_bytes())
+            .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid group name")))?;
+        let mut grp = unsafe { std::mem::zeroed::<libc::group>() };
+        let mut result = std::ptr::null_mut();
+        self.lookup(|buf| unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        })?;
+        let gid = if result.is_null() {
+            None
+        } else {
+            Some(grp.gr_gid)
+        };
+        self.wipe();
+        self.groups.insert(group.to_os_string(), gid);
+        Ok(gid)
+    }
+
+    /// Return `true` if a group with the ID `gid` exists
+    pub(crate) fn group_exists(&mut self, gid: libc::gid_t) -> Result<bool, PrivDropError> {
+        if let Some(exists) = self.gids.get(&gid) {
+            return Ok(*exists);
+        }
+        let mut grp = unsafe { std::mem::zeroed::<libc::group>() };
+        let mut result = std::ptr::null_mut();
+        self.lookup(|buf| unsafe {
+            libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result)
+        })?;
+        let exists = !result.is_null();
+        self.wipe();
+        self.gids.insert(gid, exists);
+        Ok(exists)
+    }
+
+    /// Add the records found by another resolver to the cache
+    #[cfg(feature = "groups")]
+    pub(crate) fn merge(&mut self, other: Resolver) {
+        self.users.extend(other.users);
+        self.groups.extend(other.groups);
+        self.uids.extend(other.uids);
+        self.gids.extend(other.gids);
+    }
+
+    fn lookup<F: FnMut(&mut [libc::c_char]) -> libc::c_int>(
+        &mut self,
+        mut f: F,
+    ) -> Result<(), PrivDropError> {
+        let max_buf_len = self.max_buf_len.unwrap_or(DEFAULT_MAX_BUF_LEN);
+        let max_retries = self.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
+        if self.buf.is_empty() {
+            self.buf.resize(INITIAL_BUF_LEN.min(max_buf_len), 0);
+        }
+        let mut retries = 0;
+        loop {
+            match f(&mut self.buf) {
+                0 => return Ok(()),
+                libc::ERANGE => {
+                    if self.buf.len() >= max_buf_len || retries >= max_retries {
+                        self.wipe();
+                        return Err(PrivDropError::from((
+                            ErrorKind::LookupLimit,
+                            "The name service requires a larger buffer than allowed",
+                        )));
+                    }
+                    let len = (self.buf.len() * 2).min(max_buf_len);
+                    // Growing the buffer may move it, leaving the partial record behind.
+                    self.wipe();
+                    self.buf.resize(len, 0);
+                    retries += 1;
+                }
+                ret => {
+                    self.wipe();
+                    return Err(Errno::from_raw(ret).into());
+                }
+            }
+        }
+    }
+
+    /// Overwrite the content of the lookup buffer
+    #[cfg(feature = "zeroize")]
+    fn wipe(&mut self) {
+        self.buf.as_mut_slice().zeroize();
+    }
+
+    /// Overwrite the content of the lookup buffer
+    #[cfg(not(feature = "zeroize"))]
+    fn wipe(&mut self) {}
+}
@@ -0,0 +1,197 @@
+use nix::errno::Errno;
+
+use super::errors::*;
+use super::privdrop::*;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_parse_status() {
+    let status = "Name:\tbash\nUmask:\t0022\nState:\tS (sleeping)\nTgid:\t1234\n\
+                  Uid:\t1000\t1000\t1000\t1000\nGid:\t100\t100\t100\t100\n\
+                  Threads:\t3\n";
+    assert_eq!(parse_status(status), Some((1000, 3)));
+    assert_eq!(parse_status("Name:\tkworker\nUid:\t0\t0\t0\t0\n"), None);
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[test]
+fn test_count_processes_for_forked_child() {
+    // No process runs as this user, so only the process switching to it is counted.
+    let unused_uid = 0x7fff_fff0;
+    assert_eq!(count_processes(unused_uid, true).unwrap(), 1);
+    assert!(count_processes(unused_uid, false).unwrap() >= 1);
+}
+
+/// Restrict the process to the given CPUs
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_cpu_affinity(cpus: &[usize]) -> Result<(), PrivDropError> {
+    let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+    for &cpu in cpus {
+        if cpu >= libc::CPU_SETSIZE as usize {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "Invalid CPU number",
+            )));
+        }
+        unsafe { libc::CPU_SET(cpu, &mut set) };
+    }
+    Errno::result(unsafe {
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    })?;
+    Ok(())
+}
+
+/// Restrict the process to the given CPUs
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_cpu_affinity(_cpus: &[usize]) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Setting the CPU affinity is not supported on this platform",
+    )))
+}
+
+/// Limit the number of processes and threads of the real user ID of the process, hard limit
+/// included
+pub(crate) fn set_process_limit(limit: libc::rlim_t) -> Result<(), PrivDropError> {
+    let rlim = libc::rlimit {
+        rlim_cur: limit,
+        rlim_max: limit,
+    };
+    Errno::result(unsafe { libc::setrlimit(libc::RLIMIT_NPROC, &rlim) })?;
+    Ok(())
+}
+
+/// Count the processes and threads that will run as `uid` once this process has switched to it
+///
+/// This process is included, whatever its current user is. If `forked` is set, the switch is
+/// made by a child that hasn't been forked yet, which is counted as a single thread instead.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn count_processes(
+    uid: libc::uid_t,
+    forked: bool,
+) -> Result<libc::rlim_t, PrivDropError> {
+    let entries = std::fs::read_dir("/proc").map_err(|_| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to count the processes of the target user",
+        ))
+    })?;
+    let own_pid = std::process::id().to_string();
+    let mut count = 0;
+    for entry in entries.flatten() {
+        let pid = entry.file_name();
+        let pid = match pid.to_str() {
+            Some(pid) if pid.bytes().all(|c| c.is_ascii_digit()) => pid,
+            _ => continue,
+        };
+        // Processes may exit while the list is being read.
+        let status = match std::fs::read_to_string(entry.path().join("status")) {
+            Ok(status) => status,
+            Err(_) => continue,
+        };
+        match parse_status(&status) {
+            Some((_, threads)) if pid == own_pid && !forked => count += threads,
+            Some((real_uid, threads)) if real_uid == uid => count += threads,
+            _ => {}
+        }
+    }
+    if forked {
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn count_processes(
+    _uid: libc::uid_t,
+    _forked: bool,
+) -> Result<libc::rlim_t, PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Counting the processes of a user is not supported on this platform",
+    )))
+}
+
+/// Extract the real user ID and the number of threads from `/proc/<pid>/status`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn parse_status(status: &str) -> Option<(libc::uid_t, libc::rlim_t)> {
+    let mut uid = None;
+    let mut threads = None;
+    for line in status.lines() {
+        if let Some(ids) = line.strip_prefix("Uid:") {
+            uid = ids.split_whitespace().next()?.parse().ok();
+        } else if let Some(count) = line.strip_prefix("Threads:") {
+            threads = count.trim().parse().ok();
+        }
+    }
+    Some((uid?, threads?))
+}
+
+/// Set a realtime scheduling policy for the process
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_sched_policy(
+    policy: SchedPolicy,
+    priority: libc::c_int,
+) -> Result<(), PrivDropError> {
+    let policy = match policy {
+        SchedPolicy::Fifo => libc::SCHED_FIFO,
+        SchedPolicy::RoundRobin => libc::SCHED_RR,
+    };
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    Errno::result(unsafe { libc::sched_setscheduler(0, policy, &param) })?;
+    Ok(())
+}
+
+/// Set a realtime scheduling policy for the process
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_sched_policy(
+    _policy: SchedPolicy,
+    _priority: libc::c_int,
+) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Setting the scheduling policy is not supported on this platform",
+    )))
+}
+
+#[cfg(target_os = "openbsd")]
+extern "C" {
+    fn setrtable(rtableid: libc::c_int) -> libc::c_int;
+}
+
+/// Confine the process to a routing table
+#[cfg(target_os = "openbsd")]
+pub(crate) fn set_routing_table(rtable: libc::c_int) -> Result<(), PrivDropError> {
+    if unsafe { setrtable(rtable) } != 0 {
+        return Err(nix::Error::last().into());
+    }
+    Ok(())
+}
+
+/// Confine the process to a routing table
+#[cfg(not(target_os = "openbsd"))]
+pub(crate) fn set_routing_table(_rtable: libc::c_int) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "Routing tables can only be selected on OpenBSD",
+    )))
+}
+
+/// Move the process to a new UTS namespace, and set its hostname there
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn set_hostname_in_new_namespace(hostname: &[u8]) -> Result<(), PrivDropError> {
+    Errno::result(unsafe { libc::unshare(libc::CLONE_NEWUTS) })?;
+    Errno::result(unsafe { libc::sethostname(hostname.as_ptr() as *const _, hostname.len()) })?;
+    Ok(())
+}
+
+/// Move the process to a new UTS namespace, and set its hostname there
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_hostname_in_new_namespace(_hostname: &[u8]) -> Result<(), PrivDropError> {
+    Err(PrivDropError::from((
+        ErrorKind::SysError,
+        "UTS namespaces are not supported on this platform",
+    )))
+}
@@ -0,0 +1,237 @@
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::fs::MetadataExt;
+
+use nix::unistd;
+
+use super::errors::*;
+use super::privdrop::*;
+use super::resolved::*;
+
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+const CAP_SYS_CHROOT: u32 = 18;
+
+/// Inode number of the initial PID namespace
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const PROC_PID_INIT_INO: u64 = 0xefff_fffc;
+
+/// Restrictions of the environment that can prevent privileges from being dropped
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Restrictions {
+    /// The no_new_privs flag is set, e.g. by systemd's `NoNewPrivileges=yes`
+    pub no_new_privs: bool,
+    /// The process runs in a user namespace other than the initial one
+    pub in_user_namespace: bool,
+    /// `setgroups()` has been denied in the current user namespace
+    pub setgroups_denied: bool,
+    /// The process is allowed to call chroot()
+    pub can_chroot: bool,
+    /// The process is allowed to change its gid and supplementary groups
+    pub can_setgid: bool,
+    /// The process is allowed to change its uid
+    pub can_setuid: bool,
+    /// The root directory differs from the one of the init process, i.e. the process already
+    /// runs in a chroot (Linux only, requires `/proc` to be mounted)
+    pub in_chroot: bool,
+    /// The process runs in a PID namespace other than the initial one, as in most containers
+    /// (Linux only)
+    pub in_container: bool,
+}
+
+/// Operation performed when applying the changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Changing the root directory
+    Chroot,
+    /// Changing the supplementary groups
+    SetGroups,
+    /// Changing the group ID
+    SetGid,
+    /// Changing the user ID
+    SetUid,
+}
+
+/// An operation that the current process is not allowed to perform, returned by `PrivDrop::can_drop()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingPrivilege {
+    /// The operation that would fail
+    pub operation: Operation,
+    /// What the process is missing in order to perform it
+    pub required: &'static str,
+}
+
+/// Name of the capability required for an operation, or a generic requirement on platforms
+/// without capabilities
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn required(capability: &'static str) -> &'static str {
+    capability
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn required(_capability: &'static str) -> &'static str {
+    "root privileges"
+}
+
+impl PrivDrop {
+    /// Check whether the current process has the privileges required by each operation
+    ///
+    /// Names are resolved, but nothing is changed. An empty list means that all the
+    /// configured operations are permitted.
+    pub fn can_drop(&self) -> Result<Vec<MissingPrivilege>, PrivDropError> {
+        let resolved = self.resolve()?;
+        Ok(Restrictions::detect().missing_privileges(&resolved))
+    }
+}
+
+impl Restrictions {
+    /// Inspect the current process and its environment
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn detect() -> Self {
+        let no_new_privs = unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) } == 1;
+        let in_user_namespace = std::fs::read_to_string("/proc/self/uid_map")
+            .map(|uid_map| {
+                let fields: Vec<_> = uid_map.split_whitespace().collect();
+                fields != ["0", "0", "4294967295"]
+            })
+            .unwrap_or(false);
+        let setgroups_denied = std::fs::read_to_string("/proc/self/setgroups")
+            .map(|setgroups| setgroups.trim() == "deny")
+            .unwrap_or(false);
+        let cap_eff = std::fs::read_to_string("/proc/self/status")
+            .ok()
+            .and_then(|status| {
+                status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("CapEff:"))
+                    .and_then(|x| u64::from_str_radix(x.trim(), 16).ok())
+            });
+        let has_cap = |cap: u32| match cap_eff {
+            Some(cap_eff) => cap_eff & (1 << cap) != 0,
+            None => unistd::geteuid().is_root(),
+        };
+        let in_chroot = match (std::fs::metadata("/"), std::fs::metadata("/proc/1/root")) {
+            (Ok(root), Ok(init_root)) => {
+                (root.dev(), root.ino()) != (init_root.dev(), init_root.ino())
+            }
+            // Mount points are listed relative to the root directory, so none of them is `/`
+            // in a chroot, unless the new root is itself a mount point.
+            _ => std::fs::read_to_string("/proc/self/mountinfo")
+                .map(|mountinfo| {
+                    !mountinfo
+                        .lines()
+                        .any(|line| line.split(' ').nth(4) == Some("/"))
+                })
+                .unwrap_or(false),
+        };
+        let in_container = std::fs::metadata("/proc/self/ns/pid")
+            .map(|pid_ns| pid_ns.ino() != PROC_PID_INIT_INO)
+            .unwrap_or(false);
+        Restrictions {
+            no_new_privs,
+            in_user_namespace,
+            setgroups_denied,
+            can_chroot: has_cap(CAP_SYS_CHROOT),
+            can_setgid: has_cap(CAP_SETGID) && !setgroups_denied,
+            can_setuid: has_cap(CAP_SETUID),
+            in_chroot,
+            in_container,
+        }
+    }
+
+    /// Inspect the current process and its environment
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn detect() -> Self {
+        let is_root = unistd::geteuid().is_root();
+        Restrictions {
+            no_new_privs: false,
+            in_user_namespace: false,
+            setgroups_denied: false,
+            can_chroot: is_root,
+            can_setgid: is_root,
+            can_setuid: is_root,
+            in_chroot: false,
+            in_container: false,
+        }
+    }
+
+    fn missing_privileges(&self, resolved: &ResolvedPrivDrop) -> Vec<MissingPrivilege> {
+        let mut missing = vec![];
+        let mut require = |operation, required| {
+            missing.push(MissingPrivilege {
+                operation,
+                required,
+            })
+        };
+        #[cfg(feature = "chroot")]
+        if resolved.has_chroot() && !self.can_chroot {
+            require(Operation::Chroot, required("CAP_SYS_CHROOT"));
+        }
+        if resolved.gid.is_some() {
+            if self.setgroups_denied {
+                require(
+                    Operation::SetGroups,
+                    "setgroups() to be allowed in the user namespace",
+                );
+            } else if !self.can_setgid {
+                require(Operation::SetGroups, required("CAP_SETGID"));
+            }
+            if !self.can_setgid && !self.setgroups_denied {
+                require(Operation::SetGid, required("CAP_SETGID"));
+            }
+        }
+        if resolved.uid.is_some() && !self.can_setuid {
+            require(Operation::SetUid, required("CAP_SETUID"));
+        }
+        missing
+    }
+
+    /// Fail early with a targeted error if the resolved changes can't be applied,
+    /// or, if `adapt` is set, skip the operations that are not permitted
+    pub(crate) fn check(
+        &self,
+        resolved: &mut ResolvedPrivDrop,
+        adapt: bool,
+    ) -> Result<(), PrivDropError> {
+        #[cfg(feature = "chroot")]
+        if resolved.has_chroot() && !self.can_chroot {
+            if !adapt {
+                return Err(PrivDropError::from((
+                    ErrorKind::PermissionDenied,
+                    if self.in_user_namespace {
+                        "chroot() is not permitted in this user namespace (CAP_SYS_CHROOT is missing)"
+                    } else {
+                        "chroot() is not permitted (CAP_SYS_CHROOT is missing)"
+                    },
+                )));
+            }
+            resolved.chroot = None;
+            resolved.chroot_fd = None;
+        }
+        if resolved.gid.is_some() && self.setgroups_denied {
+            if !adapt {
+                return Err(PrivDropError::from((
+                    ErrorKind::PermissionDenied,
+                    "setgroups() has been denied in this user namespace",
+                )));
+            }
+            resolved.skip_setgroups = true;
+        }
+        if resolved.gid.is_some() && !self.can_setgid && !self.setgroups_denied {
+            return Err(PrivDropError::from((
+                ErrorKind::PermissionDenied,
+                "Changing the group is not permitted (CAP_SETGID is missing)",
+            )));
+        }
+        if resolved.uid.is_some() && !self.can_setuid {
+            return Err(PrivDropError::from((
+                ErrorKind::PermissionDenied,
+                if self.no_new_privs {
+                    "Changing the user is not permitted (CAP_SETUID is missing, and no_new_privs is set)"
+                } else {
+                    "Changing the user is not permitted (CAP_SETUID is missing)"
+                },
+            )));
+        }
+        Ok(())
+    }
+}
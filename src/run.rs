@@ -0,0 +1,75 @@
+use nix::unistd;
+
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+
+#[test]
+fn test_try_run() {
+    let mut called = false;
+    let e = try_run(PrivDrop::default().user("privdrop-unknown-user"), || {
+        called = true
+    })
+    .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    assert!(!called);
+    assert!(!may_regain_root(&PrivDrop::default()));
+}
+
+/// Apply the changes, verify them, and only then run `f`
+///
+/// This encodes the recommended shape of `main()`: none of the application's code runs
+/// with the initial privileges. If the changes can't be applied, or if the process is still
+/// able to switch back to root, the error is printed and the process exits with the status
+/// given by `PrivDropError::exit_code()`, without running any destructors. Use `try_run()`
+/// to handle the error instead.
+pub fn run<T, F: FnOnce() -> T>(config: PrivDrop, f: F) -> T {
+    match try_run(config, f) {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Failed to drop privileges: {}", e);
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+/// Like `run()`, but return the error instead of exiting
+pub fn try_run<T, F: FnOnce() -> T>(config: PrivDrop, f: F) -> Result<T, PrivDropError> {
+    let report = config.apply_with_report()?;
+    verify(&config, &report)?;
+    Ok(f())
+}
+
+fn verify(config: &PrivDrop, report: &AppliedPrivileges) -> Result<(), PrivDropError> {
+    if unistd::geteuid().as_raw() != report.uid || unistd::getegid().as_raw() != report.gid {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "The effective IDs don't match the real IDs",
+        )));
+    }
+    if report.uid != 0
+        && !may_regain_root(config)
+        && unistd::setuid(unistd::Uid::from_raw(0)).is_ok()
+    {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Root privileges can still be regained",
+        )));
+    }
+    Ok(())
+}
+
+/// Retained capabilities and user namespaces may legitimately allow switching to uid 0
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn may_regain_root(config: &PrivDrop) -> bool {
+    #[cfg(all(feature = "caps", target_os = "linux"))]
+    if !config.keep_caps.is_empty() {
+        return true;
+    }
+    config.unprivileged_sandbox.is_some()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn may_regain_root(_config: &PrivDrop) -> bool {
+    false
+}
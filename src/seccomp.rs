@@ -0,0 +1,181 @@
+use nix::errno::Errno;
+
+use super::errors::*;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH_NATIVE: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH_NATIVE: u32 = 0xc000_00b7;
+
+const SECCOMP_DATA_NR_OFFSET: u32 = 0;
+const SECCOMP_DATA_ARCH_OFFSET: u32 = 4;
+
+/// Set in the numbers of x32 system calls, that share the x86_64 architecture identifier
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_filter_denies_x32() {
+    let filter = filter_for_syscalls(&[libc::SYS_setuid], libc::SECCOMP_RET_ERRNO);
+    let x32_check = filter
+        .iter()
+        .position(|insn| {
+            insn.code == (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16
+                && insn.k == X32_SYSCALL_BIT
+        })
+        .expect("x32 system calls are not checked");
+    assert_eq!(filter[x32_check - 1].k, SECCOMP_DATA_NR_OFFSET);
+    assert_eq!(filter[x32_check].jt, 0);
+    assert_eq!(filter[x32_check].jf, 1);
+    assert_eq!(filter[x32_check + 1].k, libc::SECCOMP_RET_KILL_PROCESS);
+}
+
+#[test]
+fn test_filter_contents() {
+    let syscalls = [libc::SYS_setuid, libc::SYS_setgid];
+    let action = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+    let filter = filter_for_syscalls(&syscalls, action);
+    assert_eq!(filter[1].k, AUDIT_ARCH_NATIVE);
+    assert_eq!(filter[2].k, libc::SECCOMP_RET_KILL_PROCESS);
+    let checks = &filter[filter.len() - 1 - 2 * syscalls.len()..filter.len() - 1];
+    for (check, &syscall) in checks.chunks(2).zip(syscalls.iter()) {
+        assert_eq!(
+            check[0].code,
+            (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16
+        );
+        assert_eq!(
+            (check[0].k, check[0].jt, check[0].jf),
+            (syscall as u32, 0, 1)
+        );
+        assert_eq!(check[1].k, action);
+    }
+    assert_eq!(filter.last().unwrap().k, libc::SECCOMP_RET_ALLOW);
+}
+
+fn bpf_stmt(code: u32, k: u32) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as _,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn bpf_jump(code: u32, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+    libc::sock_filter {
+        code: code as _,
+        jt,
+        jf,
+        k,
+    }
+}
+
+/// Number of instructions checking the architecture and loading the system call number
+#[cfg(target_arch = "x86_64")]
+const HEADER_LEN: usize = 6;
+#[cfg(not(target_arch = "x86_64"))]
+const HEADER_LEN: usize = 4;
+
+/// Placeholder for the instructions of a filter that hasn't been filled yet
+pub(crate) const EMPTY_INSN: libc::sock_filter = libc::sock_filter {
+    code: 0,
+    jt: 0,
+    jf: 0,
+    k: 0,
+};
+
+/// Number of instructions of a filter for `nsyscalls` system calls
+pub(crate) const fn filter_len(nsyscalls: usize) -> usize {
+    HEADER_LEN + 2 * nsyscalls + 1
+}
+
+/// Build a filter returning `action` for the given system calls, and allowing everything else
+///
+/// System calls made using a foreign architecture, including x32 system calls on x86_64,
+/// are always denied.
+pub(crate) fn filter_for_syscalls(
+    syscalls: &[libc::c_long],
+    action: u32,
+) -> Vec<libc::sock_filter> {
+    let mut filter = vec![EMPTY_INSN; filter_len(syscalls.len())];
+    fill_filter(syscalls, action, &mut filter);
+    filter
+}
+
+/// Like `filter_for_syscalls()`, writing the instructions to `filter` instead of allocating
+///
+/// `filter` must hold exactly `filter_len(syscalls.len())` instructions.
+pub(crate) fn fill_filter(
+    syscalls: &[libc::c_long],
+    action: u32,
+    filter: &mut [libc::sock_filter],
+) {
+    assert_eq!(filter.len(), filter_len(syscalls.len()));
+    filter[0] = bpf_stmt(
+        libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+        SECCOMP_DATA_ARCH_OFFSET,
+    );
+    filter[1] = bpf_jump(
+        libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+        AUDIT_ARCH_NATIVE,
+        1,
+        0,
+    );
+    filter[2] = bpf_stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS);
+    filter[3] = bpf_stmt(
+        libc::BPF_LD | libc::BPF_W | libc::BPF_ABS,
+        SECCOMP_DATA_NR_OFFSET,
+    );
+    // x32 system calls would otherwise not match the native numbers below.
+    #[cfg(target_arch = "x86_64")]
+    {
+        filter[4] = bpf_jump(
+            libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K,
+            X32_SYSCALL_BIT,
+            0,
+            1,
+        );
+        filter[5] = bpf_stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_KILL_PROCESS);
+    }
+    for (i, &syscall) in syscalls.iter().enumerate() {
+        filter[HEADER_LEN + 2 * i] = bpf_jump(
+            libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K,
+            syscall as u32,
+            0,
+            1,
+        );
+        filter[HEADER_LEN + 2 * i + 1] = bpf_stmt(libc::BPF_RET | libc::BPF_K, action);
+    }
+    filter[HEADER_LEN + 2 * syscalls.len()] =
+        bpf_stmt(libc::BPF_RET | libc::BPF_K, libc::SECCOMP_RET_ALLOW);
+}
+
+/// Install a seccomp filter, and return the value returned by the kernel
+///
+/// The no_new_privs flag is set first, as required for unprivileged processes.
+pub(crate) fn install_filter(
+    filter: &mut [libc::sock_filter],
+    flags: libc::c_ulong,
+) -> Result<libc::c_long, PrivDropError> {
+    Errno::result(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    let prog = libc::sock_fprog {
+        len: filter.len() as _,
+        filter: filter.as_mut_ptr(),
+    };
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            flags,
+            &prog as *const libc::sock_fprog,
+        )
+    };
+    if ret < 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to install the seccomp filter",
+        )));
+    }
+    Ok(ret)
+}
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use super::privdrop::*;
+
+fn directory_name<'t>(path: &'t Path, base: &str) -> Option<&'t Path> {
+    path.strip_prefix(base)
+        .ok()
+        .filter(|x| !x.as_os_str().is_empty())
+}
+
+impl PrivDrop {
+    /// Translate the configuration into the equivalent systemd unit directives
+    ///
+    /// Each returned line is a `[Service]` section directive such as `User=nobody`. Options
+    /// that systemd can't express, such as directories outside of the locations it manages,
+    /// are returned as comments.
+    pub fn to_systemd_directives(&self) -> Vec<String> {
+        let mut lines = vec![];
+        if let Some(ref user) = self.user {
+            lines.push(format!("User={}", user.to_string_lossy()));
+        }
+        if let Some(ref group) = self.group {
+            lines.push(format!("Group={}", group.to_string_lossy()));
+        }
+        if self.ephemeral_ids.is_some() {
+            lines.push("DynamicUser=yes".to_string());
+        }
+        #[cfg(feature = "groups")]
+        {
+            let mut groups: Vec<String> = vec![];
+            if let Some(ref group_list) = self.group_list {
+                groups.extend(group_list.iter().map(|x| x.to_string_lossy().into_owned()));
+            }
+            if let Some(ref group_id_list) = self.group_id_list {
+                groups.extend(group_id_list.iter().map(|x| x.to_string()));
+            }
+            if !groups.is_empty() {
+                lines.push(format!("SupplementaryGroups={}", groups.join(" ")));
+            }
+        }
+        #[cfg(feature = "chroot")]
+        if let Some(ref chroot) = self.chroot {
+            lines.push(format!("RootDirectory={}", chroot.display()));
+        }
+        #[cfg(feature = "chroot")]
+        if let Some(ref workdir) = self.chroot_workdir {
+            lines.push(format!(
+                "WorkingDirectory={}",
+                Path::new("/").join(workdir).display()
+            ));
+        }
+        if self.no_new_privs {
+            lines.push("NoNewPrivileges=yes".to_string());
+        }
+        if self.disable_core_dumps {
+            lines.push("LimitCORE=0".to_string());
+        }
+        if let Some(limit) = self.max_processes {
+            lines.push(format!("LimitNPROC={}", limit));
+        }
+        if self.forbid_fork {
+            lines.push("# forbid_fork: the process limit is only known when applying".to_string());
+        }
+        if let Some(umask) = self.umask {
+            lines.push(format!("UMask={:04o}", umask));
+        }
+        if self.lock_securebits {
+            lines.push("SecureBits=noroot noroot-locked no-setuid-fixup-locked".to_string());
+        }
+        if self.deny_setuid_family {
+            lines.push("SystemCallFilter=~@setuid".to_string());
+        }
+        #[cfg(all(feature = "caps", target_os = "linux"))]
+        if !self.keep_caps.is_empty() {
+            let caps: Vec<_> = self
+                .keep_caps
+                .iter()
+                .map(|cap| {
+                    let cap = cap.to_uppercase();
+                    if cap.starts_with("CAP_") {
+                        cap
+                    } else {
+                        format!("CAP_{}", cap)
+                    }
+                })
+                .collect();
+            let caps = caps.join(" ");
+            lines.push(format!("CapabilityBoundingSet={}", caps));
+            lines.push(format!("AmbientCapabilities={}", caps));
+        }
+        if let Some(ref cpus) = self.cpu_affinity {
+            let cpus: Vec<_> = cpus.iter().map(|cpu| cpu.to_string()).collect();
+            lines.push(format!("CPUAffinity={}", cpus.join(" ")));
+        }
+        if let Some((policy, priority)) = self.sched_policy {
+            let policy = match policy {
+                SchedPolicy::Fifo => "fifo",
+                SchedPolicy::RoundRobin => "rr",
+            };
+            lines.push(format!("CPUSchedulingPolicy={}", policy));
+            lines.push(format!("CPUSchedulingPriority={}", priority));
+        }
+        let directories = [
+            (&self.runtime_dir, "/run", "RuntimeDirectory"),
+            (&self.state_dir, "/var/lib", "StateDirectory"),
+            (&self.cache_dir, "/var/cache", "CacheDirectory"),
+        ];
+        for (dir, base, directive) in directories.iter() {
+            if let Some((ref path, mode)) = dir {
+                match directory_name(path, base) {
+                    Some(name) => {
+                        lines.push(format!("{}={}", directive, name.display()));
+                        lines.push(format!("{}Mode={:04o}", directive, mode));
+                    }
+                    None => lines.push(format!(
+                        "# {}: {} is not under {}",
+                        directive,
+                        path.display(),
+                        base
+                    )),
+                }
+            }
+        }
+        if let (true, Some((ref path, _))) = (self.chdir_to_runtime_dir, &self.runtime_dir) {
+            lines.push(format!("WorkingDirectory={}", path.display()));
+        }
+        lines
+    }
+}
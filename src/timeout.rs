@@ -0,0 +1,160 @@
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+use super::resolved::*;
+
+#[test]
+fn test_child_error_is_reported() {
+    let config = PrivDrop::default().user("privdrop-unknown-user");
+    let expected = config.resolve().unwrap_err();
+    let e = config
+        .apply_with_timeout(Duration::from_secs(30))
+        .unwrap_err();
+    assert_eq!(e.kind(), expected.kind());
+    assert_eq!(e.to_string(), expected.to_string());
+}
+
+const RESOLVER_FAILED: libc::c_int = 1;
+const RESOLVER_WRITE_FAILED: libc::c_int = 2;
+
+impl PrivDrop {
+    /// Apply the changes, giving up if names can't be resolved before the deadline
+    ///
+    /// Name lookups, as well as accessing the chroot directory, are performed in a short-lived
+    /// child process. If that process doesn't complete within `timeout`, it is killed and an
+    /// error of kind `ErrorKind::Timeout` is returned, leaving the current process unchanged.
+    /// If the lookups fail, the error reported by the child process is returned.
+    /// As with any use of `fork()`, NSS modules may misbehave if the process is multithreaded.
+    pub fn apply_with_timeout(
+        &self,
+        timeout: Duration,
+    ) -> Result<AppliedPrivileges, PrivDropError> {
+        Self::preload()?;
+        let start = Instant::now();
+        let deadline = start + timeout;
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(nix::Error::last().into());
+        }
+        let (reader, writer) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(nix::Error::last().into());
+        }
+        if pid == 0 {
+            drop(reader);
+            let (status, serialized) = match self.resolve_and_probe() {
+                Ok(serialized) => (0, serialized),
+                Err(e) => (RESOLVER_FAILED, e.serialize()),
+            };
+            let status = if write_all(writer.as_raw_fd(), serialized.as_bytes()) {
+                status
+            } else {
+                RESOLVER_WRITE_FAILED
+            };
+            unsafe { libc::_exit(status) };
+        }
+        drop(writer);
+        let serialized = read_until_deadline(reader.as_raw_fd(), deadline);
+        let serialized = match serialized {
+            Some(serialized) => serialized,
+            None => {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+                let mut status = 0;
+                // A process blocked in an uninterruptible sleep can't be reaped yet.
+                unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+                return Err(PrivDropError::from((
+                    ErrorKind::Timeout,
+                    "Timeout while resolving the credentials",
+                )));
+            }
+        };
+        let mut status = 0;
+        while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+            let e = nix::Error::last();
+            if e != nix::Error::EINTR {
+                return Err(e.into());
+            }
+        }
+        let resolved = match (libc::WIFEXITED(status), libc::WEXITSTATUS(status)) {
+            (true, 0) => ResolvedPrivDrop::deserialize(&serialized)?,
+            // The lookups completed but failed: report the error of the child.
+            (true, RESOLVER_FAILED) => {
+                return Err(PrivDropError::deserialize(&serialized).unwrap_or_else(|| {
+                    PrivDropError::from((ErrorKind::SysError, "Unable to resolve the credentials"))
+                }))
+            }
+            _ => {
+                return Err(PrivDropError::from((
+                    ErrorKind::SysError,
+                    "The process resolving the credentials failed",
+                )))
+            }
+        };
+        // Descriptors are not serialized: the parent already has its own.
+        #[cfg(feature = "chroot")]
+        let resolved = ResolvedPrivDrop {
+            chroot_fd: self.chroot_fd.clone(),
+            ..resolved
+        };
+        self.apply_resolved(resolved, start.elapsed(), || self.open_files())
+            .map(|(report, _)| report)
+    }
+
+    fn resolve_and_probe(&self) -> Result<String, PrivDropError> {
+        let resolved = self.resolve()?;
+        #[cfg(feature = "chroot")]
+        if let Some(ref chroot) = resolved.chroot {
+            let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+            if unsafe { libc::stat(chroot.as_ptr(), &mut st) } != 0 {
+                return Err(nix::Error::last().into());
+            }
+        }
+        Ok(resolved.serialize())
+    }
+}
+
+fn write_all(fd: libc::c_int, mut buf: &[u8]) -> bool {
+    while !buf.is_empty() {
+        let written = unsafe { libc::write(fd, buf.as_ptr() as *const _, buf.len()) };
+        if written < 0 {
+            if nix::Error::last() == nix::Error::EINTR {
+                continue;
+            }
+            return false;
+        }
+        buf = &buf[written as usize..];
+    }
+    true
+}
+
+fn read_until_deadline(fd: libc::c_int, deadline: Instant) -> Option<String> {
+    let mut data = vec![];
+    let mut buf = [0u8; 4096];
+    loop {
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = remaining.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pfd, 1, timeout_ms) } {
+            0 => return None,
+            n if n < 0 && nix::Error::last() == nix::Error::EINTR => continue,
+            n if n < 0 => return None,
+            _ => {}
+        }
+        let len = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+        match len {
+            0 => return Some(String::from_utf8(data).unwrap_or_default()),
+            len if len < 0 && nix::Error::last() == nix::Error::EINTR => continue,
+            len if len < 0 => return Some(String::new()),
+            len => data.extend_from_slice(&buf[..len as usize]),
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::time::Instant;
+
+#[cfg(feature = "rustls")]
+use rustls_pki_types::pem::PemObject;
+#[cfg(feature = "rustls")]
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+use super::audit;
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+use super::resolved::*;
+
+/// A certificate chain and its private key, read with the initial privileges
+///
+/// The content of the files is kept as is, usually PEM or DER. The private key is not
+/// included in the `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TlsKeyPair {
+    cert_chain: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+impl fmt::Debug for TlsKeyPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsKeyPair")
+            .field("cert_chain", &self.cert_chain.len())
+            .field("private_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl TlsKeyPair {
+    /// Content of the certificate chain file
+    pub fn cert_chain(&self) -> &[u8] {
+        &self.cert_chain
+    }
+
+    /// Content of the private key file
+    pub fn private_key(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    /// Parse the certificate chain, either as PEM or as a single DER certificate
+    #[cfg(feature = "rustls")]
+    pub fn rustls_cert_chain(&self) -> Result<Vec<CertificateDer<'static>>, PrivDropError> {
+        if !is_pem(&self.cert_chain) {
+            return Ok(vec![CertificateDer::from(self.cert_chain.clone())]);
+        }
+        CertificateDer::pem_slice_iter(&self.cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| {
+                PrivDropError::from((ErrorKind::InvalidConfig, "Invalid certificate chain"))
+            })
+    }
+
+    /// Parse the private key, either as PEM or as a DER-encoded PKCS#8 key
+    #[cfg(feature = "rustls")]
+    pub fn rustls_private_key(&self) -> Result<PrivateKeyDer<'static>, PrivDropError> {
+        if !is_pem(&self.private_key) {
+            return Ok(PrivatePkcs8KeyDer::from(self.private_key.clone()).into());
+        }
+        PrivateKeyDer::from_pem_slice(&self.private_key)
+            .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid private key")))
+    }
+}
+
+#[cfg(feature = "rustls")]
+fn is_pem(content: &[u8]) -> bool {
+    content.windows(10).any(|x| x == b"-----BEGIN")
+}
+
+/// Read a file, checking first that it won't be readable with the target credentials
+fn read_protected(path: &Path, resolved: &ResolvedPrivDrop) -> Result<Vec<u8>, PrivDropError> {
+    let mut file = File::open(path)
+        .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Unable to open a TLS key file")))?;
+    if let Some(uid) = resolved.uid {
+        let mut st = unsafe { std::mem::zeroed::<libc::stat>() };
+        if unsafe { libc::fstat(file.as_raw_fd(), &mut st) } != 0 {
+            return Err(nix::Error::last().into());
+        }
+        let gid = resolved.gid.unwrap_or_else(|| unsafe { libc::getgid() });
+        if audit::is_readable(&st, uid, gid, &resolved.groups) {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "A TLS private key would remain readable after dropping privileges",
+            )));
+        }
+    }
+    let mut content = vec![];
+    file.read_to_end(&mut content)
+        .map_err(|_| PrivDropError::from((ErrorKind::SysError, "Unable to read a TLS key file")))?;
+    Ok(content)
+}
+
+impl PrivDrop {
+    /// Apply the changes, and return the certificate chains and private keys declared with
+    /// `tls_key_pair()` along with the report
+    ///
+    /// The files are read with the initial privileges, right before changing the root
+    /// directory. If the user changes, the private keys must not be readable by the target
+    /// user and groups, so that they can't be read again by a compromised process.
+    pub fn apply_with_tls_key_pairs(
+        &self,
+    ) -> Result<(AppliedPrivileges, Vec<TlsKeyPair>), PrivDropError> {
+        let res = Self::preload().and_then(|_| {
+            let start = Instant::now();
+            let resolved = self.resolve()?;
+            let resolution = start.elapsed();
+            let target = resolved.clone();
+            self.apply_resolved(resolved, resolution, || {
+                let mut key_pairs = Vec::with_capacity(self.tls_key_pairs.len());
+                for (cert_chain, private_key) in &self.tls_key_pairs {
+                    let cert_chain = std::fs::read(cert_chain).map_err(|_| {
+                        PrivDropError::from((
+                            ErrorKind::SysError,
+                            "Unable to read a TLS certificate chain",
+                        ))
+                    })?;
+                    let private_key = read_protected(private_key, &target)?;
+                    key_pairs.push(TlsKeyPair {
+                        cert_chain,
+                        private_key,
+                    });
+                }
+                Ok(key_pairs)
+            })
+        });
+        res.map_err(|e| self.explain(e))
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+use nix::errno::Errno;
+use nix::unistd;
+
+use super::errors::*;
+use super::mounts;
+use super::privdrop::*;
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn write_proc_file(path: &str, content: &str) -> io::Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+}
+
+/// Create a new user and mount namespace, mapping the current uid and gid to `ns_uid` and `ns_gid`
+pub(crate) fn enter_user_namespace(
+    ns_uid: libc::uid_t,
+    ns_gid: libc::gid_t,
+    propagation: MountPropagation,
+) -> Result<(), PrivDropError> {
+    let uid = unistd::geteuid().as_raw();
+    let gid = unistd::getegid().as_raw();
+    if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS) } != 0 {
+        return Err(PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to create a user namespace",
+        )));
+    }
+    // An unprivileged process has to deny setgroups() before it can write the gid map, so
+    // supplementary groups can't be changed afterwards. Kernels older than 3.19 don't have
+    // this file, and don't require it.
+    match write_proc_file("/proc/self/setgroups", "deny") {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(PrivDropError::from((
+                ErrorKind::SysError,
+                "Unable to deny setgroups() in the user namespace",
+            )))
+        }
+        _ => {}
+    }
+    let mappings_error = |_| {
+        PrivDropError::from((
+            ErrorKind::SysError,
+            "Unable to write the user namespace mappings",
+        ))
+    };
+    write_proc_file("/proc/self/uid_map", &format!("{} {} 1\n", ns_uid, uid))
+        .map_err(mappings_error)?;
+    write_proc_file("/proc/self/gid_map", &format!("{} {} 1\n", ns_gid, gid))
+        .map_err(mappings_error)?;
+    mounts::isolate_mounts(propagation)
+}
+
+/// Irrevocably drop all the capabilities the process holds in its user namespace
+pub(crate) fn drop_all_capabilities() -> Result<(), PrivDropError> {
+    for cap in 0..64 {
+        if unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap, 0, 0, 0) } != 0
+            && Errno::last() != Errno::EINVAL
+        {
+            return Err(nix::Error::last().into());
+        }
+    }
+    unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL,
+            0,
+            0,
+            0,
+        )
+    };
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let data = [CapUserData::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) } != 0 {
+        return Err(nix::Error::last().into());
+    }
+    Errno::result(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+    Ok(())
+}
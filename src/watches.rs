@@ -0,0 +1,105 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use nix::errno::Errno;
+
+use super::errors::*;
+use super::privdrop::*;
+use super::report::*;
+
+/// Watch declared with `PrivDrop::inotify_watch()` or `PrivDrop::fanotify_mark()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Watch {
+    Inotify(PathBuf, u32),
+    Fanotify(PathBuf, libc::c_uint, u64),
+}
+
+/// Notification descriptors set up by `PrivDrop::apply_with_watches()`
+#[derive(Debug, Default)]
+pub struct Watches {
+    /// inotify instance holding the watches declared with `inotify_watch()`
+    pub inotify: Option<OwnedFd>,
+    /// inotify watch descriptors, in the order the watches were declared
+    pub inotify_watches: Vec<libc::c_int>,
+    /// fanotify group holding the marks declared with `fanotify_mark()`
+    pub fanotify: Option<OwnedFd>,
+}
+
+fn c_path(path: &Path) -> Result<CString, PrivDropError> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| PrivDropError::from((ErrorKind::InvalidConfig, "Invalid watched path")))
+}
+
+/// Create the inotify instance and the fanotify group, and add the watches and marks
+///
+/// The fanotify group is created in the content class if permission events are requested,
+/// as required by the kernel, and in the notification class otherwise.
+fn set_up(watches: &[Watch]) -> Result<Watches, PrivDropError> {
+    let mut res = Watches::default();
+    let permission_events = libc::FAN_OPEN_PERM | libc::FAN_ACCESS_PERM | libc::FAN_OPEN_EXEC_PERM;
+    let permission_events_requested = watches
+        .iter()
+        .any(|watch| matches!(watch, Watch::Fanotify(_, _, mask) if mask & permission_events != 0));
+    let fanotify_class = if permission_events_requested {
+        libc::FAN_CLASS_CONTENT
+    } else {
+        libc::FAN_CLASS_NOTIF
+    };
+    for watch in watches {
+        match watch {
+            Watch::Inotify(path, mask) => {
+                let path = c_path(path)?;
+                if res.inotify.is_none() {
+                    let fd = Errno::result(unsafe { libc::inotify_init1(libc::IN_CLOEXEC) })?;
+                    res.inotify = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+                }
+                let fd = res.inotify.as_ref().map_or(-1, |fd| fd.as_raw_fd());
+                let wd =
+                    Errno::result(unsafe { libc::inotify_add_watch(fd, path.as_ptr(), *mask) })?;
+                res.inotify_watches.push(wd);
+            }
+            Watch::Fanotify(path, flags, mask) => {
+                let path = c_path(path)?;
+                if res.fanotify.is_none() {
+                    let fd = Errno::result(unsafe {
+                        libc::fanotify_init(
+                            libc::FAN_CLOEXEC | fanotify_class,
+                            (libc::O_RDONLY | libc::O_LARGEFILE | libc::O_CLOEXEC) as libc::c_uint,
+                        )
+                    })?;
+                    res.fanotify = Some(unsafe { OwnedFd::from_raw_fd(fd) });
+                }
+                let fd = res.fanotify.as_ref().map_or(-1, |fd| fd.as_raw_fd());
+                Errno::result(unsafe {
+                    libc::fanotify_mark(
+                        fd,
+                        libc::FAN_MARK_ADD | flags,
+                        *mask,
+                        libc::AT_FDCWD,
+                        path.as_ptr(),
+                    )
+                })?;
+            }
+        }
+    }
+    Ok(res)
+}
+
+impl PrivDrop {
+    /// Apply the changes, and return the notification descriptors holding the watches declared
+    /// with `inotify_watch()` and `fanotify_mark()` along with the report
+    ///
+    /// The watches are set up with the initial privileges, right before changing the root
+    /// directory. Events keep being reported for paths that the target user can't access.
+    pub fn apply_with_watches(&self) -> Result<(AppliedPrivileges, Watches), PrivDropError> {
+        let res = Self::preload().and_then(|_| {
+            let start = Instant::now();
+            let resolved = self.resolve()?;
+            self.apply_resolved(resolved, start.elapsed(), || set_up(&self.watches))
+        });
+        res.map_err(|e| self.explain(e))
+    }
+}
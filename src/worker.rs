@@ -0,0 +1,166 @@
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd};
+
+use super::errors::*;
+use super::privdrop::*;
+use super::resolved::*;
+
+#[test]
+fn test_apply_in_child_runs_checks() {
+    assert!(PrivDrop::default()
+        .audit_home_dir()
+        .apply_in_child(|| 0)
+        .is_err());
+    let e = PrivDrop::default()
+        .check_executable_not_writable()
+        .apply_in_child(|| 0)
+        .unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::InvalidConfig);
+    let worker = PrivDrop::default().apply_in_child(|| 42).unwrap();
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    assert!(worker.pidfd().is_some());
+    let status = worker.wait().unwrap();
+    assert!(libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 42);
+}
+
+/// Exit status of a worker that couldn't drop its privileges
+pub const WORKER_DROP_FAILED: i32 = 126;
+
+/// An unprivileged worker process created by `PrivDrop::apply_in_child()`
+#[derive(Debug)]
+pub struct Worker {
+    pid: libc::pid_t,
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pidfd: Option<OwnedFd>,
+}
+
+impl Worker {
+    /// Process identifier of the worker
+    pub fn pid(&self) -> libc::pid_t {
+        self.pid
+    }
+
+    /// File descriptor referring to the worker process, if the kernel supports pidfds
+    ///
+    /// Unlike the PID, the pidfd can't be recycled, so it can be polled or used to send
+    /// signals without races.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn pidfd(&self) -> Option<BorrowedFd<'_>> {
+        self.pidfd.as_ref().map(|x| x.as_fd())
+    }
+
+    /// Wait for the worker to terminate, and return its raw wait status
+    pub fn wait(self) -> Result<libc::c_int, PrivDropError> {
+        let mut status = 0;
+        loop {
+            if unsafe { libc::waitpid(self.pid, &mut status, 0) } >= 0 {
+                return Ok(status);
+            }
+            let e = nix::Error::last();
+            if e != nix::Error::EINTR {
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pidfd_open(pid: libc::pid_t) -> Option<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return None;
+    }
+    Some(unsafe { OwnedFd::from_raw_fd(fd as _) })
+}
+
+/// Pipe used to tell a new worker that its pidfd has been opened
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn ready_pipe() -> Result<(OwnedFd, OwnedFd), PrivDropError> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(nix::Error::last().into());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Only performs async-signal-safe operations, as it runs in a forked child
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn wait_until_ready(reader: &OwnedFd) {
+    let mut buf = [0u8; 1];
+    // End of file means that the parent is gone, and there's no pidfd to wait for.
+    while unsafe { libc::read(reader.as_raw_fd(), buf.as_mut_ptr() as *mut _, 1) } < 0
+        && nix::Error::last() == nix::Error::EINTR
+    {}
+}
+
+impl PrivDrop {
+    /// Fork a worker process that applies the changes, then runs `f`
+    ///
+    /// Names are resolved, and the checks performed, in the parent process, which returns an
+    /// error if any of them fails. The child only performs the system calls applying the
+    /// changes, then exits with the value returned by `f`, or with `WORKER_DROP_FAILED` if the
+    /// changes couldn't be applied. Changes that can't be applied without allocating, such as
+    /// `use_initgroups()`, `check_executable_not_writable()` or `unprivileged_sandbox()`, are
+    /// rejected. An ephemeral ID remains locked until the child exits. As with any use of
+    /// `fork()`, `f` should only perform async-signal-safe operations if the parent process
+    /// is multithreaded.
+    ///
+    /// The child waits for its pidfd to be opened before doing anything, so that it can't
+    /// exit and be reaped before. It can still be reaped before that if it is killed by a
+    /// signal in the meantime, and another thread waits for any child or `SIGCHLD` is ignored.
+    pub fn apply_in_child<F: FnOnce() -> i32>(&self, f: F) -> Result<Worker, PrivDropError> {
+        Self::preload()?;
+        let mut resolved = self.resolve()?;
+        self.check_fork_safe(&resolved)?;
+        // The child inherits the lock, and keeps holding it after the parent drops its copy.
+        let (_ephemeral_lock, _) = self.prepare(&mut resolved, true)?;
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let (ready_reader, ready_writer) = ready_pipe()?;
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(nix::Error::last().into());
+        }
+        if pid == 0 {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            {
+                drop(ready_writer);
+                wait_until_ready(&ready_reader);
+                drop(ready_reader);
+            }
+            let status = match self.apply_prepared(&resolved) {
+                Ok(()) => f(),
+                Err(_) => WORKER_DROP_FAILED,
+            };
+            unsafe { libc::_exit(status) };
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let pidfd = {
+            drop(ready_reader);
+            let pidfd = pidfd_open(pid);
+            unsafe { libc::write(ready_writer.as_raw_fd(), b"\0".as_ptr() as *const _, 1) };
+            pidfd
+        };
+        Ok(Worker {
+            pid,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            pidfd,
+        })
+    }
+
+    /// Reject the changes that can't be applied right after `fork()`
+    fn check_fork_safe(&self, resolved: &ResolvedPrivDrop) -> Result<(), PrivDropError> {
+        if self.sandbox().is_some() {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "unprivileged_sandbox() can't be applied right after fork()",
+            )));
+        }
+        if self.check_executable_not_writable {
+            return Err(PrivDropError::from((
+                ErrorKind::InvalidConfig,
+                "check_executable_not_writable() can't be applied right after fork()",
+            )));
+        }
+        resolved.check_fork_safe()
+    }
+}